@@ -1,8 +1,8 @@
 #!/bin/bash
 # Wrapper script to ensure environment variables are set correctly
 
-# Set DISPLAY if not already set
-if [ -z "$DISPLAY" ]; then
+# Set DISPLAY if not already set, unless this is a Wayland session
+if [ -z "$DISPLAY" ] && [ -z "$WAYLAND_DISPLAY" ]; then
     export DISPLAY=:0
 fi
 
@@ -0,0 +1,150 @@
+use std::env;
+use std::sync::Arc;
+
+use anyhow::Result;
+use core_lib::config::{Backend, Config};
+
+use crate::event::ClipboardEvent;
+use crate::listener::ClipboardListener;
+use crate::mock::MockClipboard;
+use crate::null::{NullClipboardListener, NullClipboardWriter};
+use crate::wayland::{WaylandClipboardListener, WaylandClipboardWriter};
+use crate::writer::{ClipboardWriter, Writers};
+use crate::x11::{X11ClipboardListener, X11ClipboardWriter};
+
+/// Create the clipboard listener for this session.
+///
+/// Uses the backend set in `config`, or picks one from the session
+/// environment when it is `auto`. `display` picks the X display to watch,
+/// `$DISPLAY` if `None`; other backends ignore it. The X11 listener uses
+/// `writer` to keep copies alive after their application exits.
+pub fn create_listener<F>(
+    config: &Config,
+    display: Option<&str>,
+    writer: Option<Arc<dyn ClipboardWriter>>,
+    on_change: F,
+) -> Result<Box<dyn ClipboardListener + Send>>
+where
+    F: Fn(ClipboardEvent) + Clone + Send + 'static,
+{
+    let (backend, auto) = select(config);
+
+    let listener: Box<dyn ClipboardListener + Send> = match backend {
+        Backend::Wayland => match WaylandClipboardListener::new(config, on_change.clone()) {
+            Ok(listener) => Box::new(listener),
+            // Compositors without data-control may still run XWayland
+            Err(e) if auto && env::var_os("DISPLAY").is_some() => {
+                eprintln!("Wayland backend unavailable: {:#}. Falling back to X11.", e);
+                println!("Clipboard backend: x11 (fallback, only X11 apps are captured)");
+                return Ok(Box::new(X11ClipboardListener::new(config, None, writer, on_change)?));
+            }
+            Err(e) => return Err(e),
+        },
+        Backend::X11 => Box::new(X11ClipboardListener::new(config, display, writer, on_change)?),
+        Backend::Mock => Box::new(MockClipboard::global().listener(on_change)),
+        Backend::Null | Backend::Auto => {
+            eprintln!("No graphical session found; clipboard changes will not be captured");
+            Box::new(NullClipboardListener::new())
+        }
+    };
+
+    let source = if auto { "auto-detected" } else { "from config" };
+    println!("Clipboard backend: {} ({})", name(backend), source);
+    Ok(listener)
+}
+
+/// Create the clipboard writer for this session, on the same backend as the listener
+pub fn create_writer(config: &Config, display: Option<&str>) -> Result<Arc<dyn ClipboardWriter>> {
+    let (backend, auto) = select(config);
+
+    Ok(match backend {
+        Backend::Wayland => match WaylandClipboardWriter::new() {
+            Ok(writer) => Arc::new(writer),
+            Err(_) if auto && env::var_os("DISPLAY").is_some() => Arc::new(X11ClipboardWriter::new(None)?),
+            Err(e) => return Err(e),
+        },
+        Backend::X11 => Arc::new(X11ClipboardWriter::new(display)?),
+        Backend::Mock => Arc::new(MockClipboard::global().writer()),
+        Backend::Null | Backend::Auto => Arc::new(NullClipboardWriter),
+    })
+}
+
+/// Create a writer for every display to watch, logging those that fail
+pub fn create_writers(config: &Config) -> Writers {
+    let mut writers = Writers::new();
+    for display in displays(config) {
+        match create_writer(config, display.as_deref()) {
+            Ok(writer) => {
+                writers.insert(display, writer);
+            }
+            Err(e) => match &display {
+                Some(name) => eprintln!("Failed to create clipboard writer for display {}: {:#}", name, e),
+                None => eprintln!("Failed to create clipboard writer: {:#}. Pasting from history is disabled.", e),
+            },
+        }
+    }
+    writers
+}
+
+/// Displays to watch: the configured X displays, or just the session's own.
+///
+/// Only the X11 backend can watch several displays.
+pub(crate) fn displays(config: &Config) -> Vec<Option<String>> {
+    if config.capture.displays.is_empty() || select(config).0 != Backend::X11 {
+        return vec![None];
+    }
+    config.capture.displays.iter().cloned().map(Some).collect()
+}
+
+/// Backend to use and whether it was picked automatically
+fn select(config: &Config) -> (Backend, bool) {
+    match config.capture.backend {
+        Backend::Auto => {
+            let backend = detect(
+                env::var_os("WAYLAND_DISPLAY").is_some(),
+                env::var_os("DISPLAY").is_some(),
+                env::var("XDG_SESSION_TYPE").ok().as_deref(),
+            );
+            (backend, true)
+        }
+        backend => (backend, false),
+    }
+}
+
+/// Pick a backend from the session environment
+fn detect(wayland_display: bool, display: bool, session_type: Option<&str>) -> Backend {
+    // An X11 session can still have WAYLAND_DISPLAY leaking in, e.g. from a nested compositor
+    if wayland_display && session_type != Some("x11") {
+        Backend::Wayland
+    } else if display {
+        Backend::X11
+    } else if wayland_display {
+        Backend::Wayland
+    } else {
+        Backend::Null
+    }
+}
+
+fn name(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Auto => "auto",
+        Backend::X11 => "x11",
+        Backend::Wayland => "wayland",
+        Backend::Null => "null",
+        Backend::Mock => "mock",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(true, true, Some("wayland")), Backend::Wayland);
+        assert_eq!(detect(true, false, None), Backend::Wayland);
+        assert_eq!(detect(true, true, Some("x11")), Backend::X11);
+        assert_eq!(detect(false, true, Some("wayland")), Backend::X11);
+        assert_eq!(detect(false, false, Some("tty")), Backend::Null);
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{bail, Result};
+
+const ESC: u8 = 0x1b;
+/// Control Sequence Introducer, used by COMPOUND_TEXT for text direction
+const CSI: u8 = 0x9b;
+
+/// Decode text transferred under `target`, an X11 atom name or MIME type
+pub(crate) fn decode_text(target: &str, bytes: Vec<u8>) -> Result<String> {
+    match target {
+        "UTF8_STRING" | "text/plain;charset=utf-8" => Ok(String::from_utf8(bytes)?),
+        "STRING" => Ok(decode_latin1(&bytes)),
+        "COMPOUND_TEXT" => decode_compound_text(&bytes),
+        // The owner picks the encoding for these; tell them apart by their content
+        "TEXT" | "text/plain" => {
+            if bytes.contains(&ESC) {
+                decode_compound_text(&bytes)
+            } else {
+                String::from_utf8(bytes).or_else(|e| Ok(decode_latin1(e.as_bytes())))
+            }
+        }
+        _ => bail!("no text decoding for {}", target),
+    }
+}
+
+/// ISO 8859-1, which maps every byte to the code point of the same value
+pub(crate) fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+/// Which character set the right half (bytes 0xA0-0xFF) currently uses
+#[derive(Clone, Copy, PartialEq)]
+enum RightHalf {
+    Latin1,
+    Unsupported,
+}
+
+/// Decode X11 COMPOUND_TEXT.
+///
+/// Supports the Latin-1 halves, which are also the initial state, and the
+/// UTF-8 segments Xlib emits for characters outside the legacy charsets.
+/// An escape sequence cut off at the end of the data ends the text, so
+/// truncated transfers still decode.
+pub(crate) fn decode_compound_text(bytes: &[u8]) -> Result<String> {
+    let mut text = String::new();
+    let mut right = RightHalf::Latin1;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            ESC => {
+                let sequence = &bytes[i + 1..];
+                match sequence {
+                    // ASCII into the left half
+                    [b'(', b'B', ..] => i += 3,
+                    // Latin-1 right half
+                    [b'-', b'A', ..] => {
+                        right = RightHalf::Latin1;
+                        i += 3;
+                    }
+                    // Other 96-character sets, e.g. ESC - B for Latin-2
+                    [b'-', _, ..] => {
+                        right = RightHalf::Unsupported;
+                        i += 3;
+                    }
+                    // UTF-8 until ESC % @
+                    [b'%', b'G', rest @ ..] => {
+                        let end = rest.windows(3).position(|w| w == [ESC, b'%', b'@']).unwrap_or(rest.len());
+                        let segment = &rest[..end];
+                        match std::str::from_utf8(segment) {
+                            Ok(s) => text.push_str(s),
+                            // A character cut off by a truncated transfer
+                            Err(e) if end == rest.len() && e.error_len().is_none() => {
+                                text.push_str(&String::from_utf8_lossy(&segment[..e.valid_up_to()]));
+                            }
+                            Err(e) => return Err(e.into()),
+                        }
+                        i += 3 + end + 3;
+                    }
+                    // Extended segment: ESC % / F M L name STX data, with M and L giving its length
+                    [b'%', b'/', _, m, l, ..] => {
+                        let length = usize::from(m & 0x7f) * 128 + usize::from(l & 0x7f);
+                        let segment = &bytes[(i + 6).min(bytes.len())..(i + 6 + length).min(bytes.len())];
+                        let Some(stx) = segment.iter().position(|&b| b == 0x02) else {
+                            break;
+                        };
+                        let name = String::from_utf8_lossy(&segment[..stx]).to_lowercase();
+                        let data = &segment[stx + 1..];
+                        match name.as_str() {
+                            "utf-8" => text.push_str(std::str::from_utf8(data)?),
+                            "iso8859-1" => text.push_str(&decode_latin1(data)),
+                            _ => bail!("unsupported COMPOUND_TEXT charset {}", name),
+                        }
+                        i += 6 + length;
+                    }
+                    [] | [_] | [b'%', ..] => break,
+                    [intermediate, final_byte, ..] => bail!(
+                        "unsupported COMPOUND_TEXT escape sequence ESC {} {}",
+                        char::from(*intermediate),
+                        char::from(*final_byte)
+                    ),
+                }
+            }
+            CSI => {
+                // Direction changes (CSI 1 ], CSI 2 ], CSI ]) carry no text
+                match bytes[i + 1..].iter().position(|&b| b == b']') {
+                    Some(end) => i += end + 2,
+                    None => break,
+                }
+            }
+            byte @ 0xa0..=0xff => {
+                if right == RightHalf::Unsupported {
+                    bail!("unsupported COMPOUND_TEXT charset");
+                }
+                text.push(char::from(byte));
+                i += 1;
+            }
+            byte => {
+                text.push(char::from(byte));
+                i += 1;
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_legacy_text() {
+        assert_eq!(decode_text("STRING", b"caf\xe9".to_vec()).unwrap(), "café");
+        assert_eq!(decode_text("TEXT", "café".as_bytes().to_vec()).unwrap(), "café");
+        assert_eq!(decode_text("TEXT", b"caf\xe9".to_vec()).unwrap(), "café");
+        assert!(decode_text("UTF8_STRING", b"caf\xe9".to_vec()).is_err());
+
+        // Latin-1 in the initial state, then a UTF-8 segment for the Cyrillic
+        let compound = b"caf\xe9 \x1b%G\xd0\xbc\xd0\xb8\xd1\x80\x1b%@!";
+        assert_eq!(decode_text("COMPOUND_TEXT", compound.to_vec()).unwrap(), "café мир!");
+        assert_eq!(decode_compound_text(b"\x1b-Ab\xfcro").unwrap(), "büro");
+        assert_eq!(decode_compound_text(b"ab\x1b%").unwrap(), "ab");
+        assert!(decode_compound_text(b"\x1b-B\xb1").is_err());
+    }
+}
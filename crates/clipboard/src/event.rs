@@ -0,0 +1,22 @@
+use std::time::SystemTime;
+
+use core_lib::buffer::{ClipboardContent, RawTarget, Selection, SourceApp};
+
+/// A clipboard change reported by a listener
+#[derive(Debug, Clone)]
+pub struct ClipboardEvent {
+    pub content: ClipboardContent,
+    /// Targets (X11) or MIME types (Wayland) the owner offered
+    pub mime_types: Vec<String>,
+    /// Verbatim data of the offered targets listed in `capture.raw_targets`
+    pub raw_targets: Vec<RawTarget>,
+    pub selection: Selection,
+    /// Application owning the selection, where the backend can tell
+    pub source: Option<SourceApp>,
+    /// X display the change happened on; `None` outside X11
+    pub display: Option<String>,
+    /// The owner marked the content as secret with a concealment hint
+    pub sensitive: bool,
+    /// When the change was captured
+    pub timestamp: SystemTime,
+}
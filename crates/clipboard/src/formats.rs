@@ -0,0 +1,338 @@
+use anyhow::{anyhow, Result};
+use core_lib::buffer::{ClipboardContent, FileList, ImageContent, RawTarget, IMAGE_MIME_TYPES};
+use core_lib::config::OversizedAction;
+
+use crate::encoding;
+
+/// Targets carrying UTF-8 plain text: the X11 atom name and the MIME type
+const UTF8_TEXT_TARGETS: &[&str] = &["UTF8_STRING", "text/plain;charset=utf-8"];
+
+/// Plain text targets in order of preference. Older X clients, Java and Wine
+/// only offer the legacy encodings at the end.
+const TEXT_TARGETS: &[&str] =
+    &["UTF8_STRING", "text/plain;charset=utf-8", "COMPOUND_TEXT", "TEXT", "STRING", "text/plain"];
+
+/// Targets we serve plain text under, as MIME types and X11 atom names
+const TEXT_OFFER_TARGETS: &[&str] = &["text/plain;charset=utf-8", "UTF8_STRING", "text/plain", "STRING", "TEXT"];
+
+/// Every target `content` can be served as, with its data, most specific first
+pub(crate) fn offered_targets(content: &ClipboardContent) -> Vec<(String, Vec<u8>)> {
+    let text_targets = |text: &str| {
+        TEXT_OFFER_TARGETS
+            .iter()
+            .map(|&target| (target.to_string(), text.as_bytes().to_vec()))
+            .collect::<Vec<_>>()
+    };
+
+    let mut targets = Vec::new();
+
+    match content {
+        ClipboardContent::Text { text, html, rtf } => {
+            if let Some(html) = html {
+                targets.push(("text/html".to_string(), html.clone().into_bytes()));
+            }
+            if let Some(rtf) = rtf {
+                targets.push(("text/rtf".to_string(), rtf.clone().into_bytes()));
+            }
+            targets.extend(text_targets(text));
+        }
+        ClipboardContent::Image(image) => targets.push((image.mime_type.clone(), image.data.clone())),
+        ClipboardContent::Files(files) => {
+            targets.push(("x-special/gnome-copied-files".to_string(), files.to_gnome_copied_files().into_bytes()));
+            targets.push(("text/uri-list".to_string(), files.to_uri_list().into_bytes()));
+            targets.extend(text_targets(&files.to_text()));
+        }
+    }
+
+    targets
+}
+
+/// Read the best content a selection owner offers.
+///
+/// `owner` names the selection owner in log messages. `targets` lists the
+/// targets the owner advertised, or `None` if it would not say, in which case
+/// text is requested blindly. `fetch` transfers one target and returns `None`
+/// if the owner refused it; it should stop reading one byte past `max_size`.
+/// Content larger than `max_size` is handled per `on_oversized`.
+pub(crate) fn read_content<F>(
+    owner: &str,
+    targets: Option<&[String]>,
+    max_size: usize,
+    on_oversized: OversizedAction,
+    mut fetch: F,
+) -> Result<Option<ClipboardContent>>
+where
+    F: FnMut(&str) -> Result<Option<Vec<u8>>>,
+{
+    let offered = |name: &str| targets.is_some_and(|t| t.iter().any(|n| n == name));
+    let fits = |bytes: &[u8], target: &str| {
+        let fits = bytes.len() <= max_size;
+        if !fits {
+            eprintln!("Skipping {} copy larger than max_entry_size ({} bytes)", target, max_size);
+        }
+        fits
+    };
+
+    // File managers also offer the paths as text, so files take precedence
+    if offered("x-special/gnome-copied-files")
+        && let Some(bytes) = fetch("x-special/gnome-copied-files")?
+        && fits(&bytes, "x-special/gnome-copied-files")
+        && let Some(files) = FileList::from_gnome_copied_files(&String::from_utf8_lossy(&bytes))
+    {
+        return Ok(Some(ClipboardContent::from(files)));
+    }
+
+    if offered("text/uri-list")
+        && let Some(bytes) = fetch("text/uri-list")?
+        && fits(&bytes, "text/uri-list")
+        && let Some(files) = FileList::from_uri_list(&String::from_utf8_lossy(&bytes))
+    {
+        return Ok(Some(ClipboardContent::from(files)));
+    }
+
+    let text_targets = match targets {
+        Some(_) => TEXT_TARGETS.iter().copied().filter(|&name| offered(name)).collect(),
+        // Owners that won't list their targets mostly predate UTF8_STRING
+        None => vec!["UTF8_STRING", "STRING"],
+    };
+
+    for target in text_targets {
+        let Some(mut bytes) = fetch(target)? else {
+            eprintln!("{} refused to convert the selection to {}", owner, target);
+            continue;
+        };
+
+        let oversized = bytes.len() > max_size;
+        if oversized && on_oversized == OversizedAction::Skip {
+            fits(&bytes, target);
+            return Ok(None);
+        }
+        let decoded = if oversized && UTF8_TEXT_TARGETS.contains(&target) {
+            truncate_utf8(bytes, max_size).ok_or_else(|| anyhow!("invalid UTF-8"))
+        } else {
+            bytes.truncate(max_size);
+            encoding::decode_text(target, bytes)
+        };
+        let mut text = match decoded {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Failed to decode {} text from {}: {:#}", target, owner, e);
+                continue;
+            }
+        };
+
+        // Legacy encodings can grow when decoded to UTF-8
+        if text.len() > max_size {
+            if on_oversized == OversizedAction::Skip {
+                fits(text.as_bytes(), target);
+                return Ok(None);
+            }
+            let mut end = max_size;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+        }
+
+        // Keep the formatted versions so re-pasting into a rich editor keeps
+        // them, as long as they fit next to the plain text
+        let mut budget = max_size - text.len();
+        let mut fetch_within_budget = |target: &str| -> Result<Option<Vec<u8>>> {
+            if !offered(target) {
+                return Ok(None);
+            }
+            Ok(fetch(target)?.filter(|bytes| bytes.len() <= budget).inspect(|bytes| budget -= bytes.len()))
+        };
+
+        let html = fetch_within_budget("text/html")?.map(decode_html);
+        let rtf = fetch_within_budget("text/rtf")?.map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+
+        return Ok(Some(ClipboardContent::Text { text, html, rtf }));
+    }
+
+    for &mime_type in IMAGE_MIME_TYPES {
+        if offered(mime_type)
+            && let Some(bytes) = fetch(mime_type)?
+            && fits(&bytes, mime_type)
+            && let Some(image) = ImageContent::from_bytes(mime_type, bytes)
+        {
+            return Ok(Some(ClipboardContent::from(image)));
+        }
+    }
+
+    if let Some(targets) = targets
+        && !targets.is_empty()
+    {
+        eprintln!("Nothing capturable offered by {} (targets: {})", owner, targets.join(", "));
+    }
+    Ok(None)
+}
+
+/// Verbatim data of the `wanted` targets the owner offered, at most `budget` bytes in total.
+///
+/// Targets that don't fit in what is left of the budget are left out.
+pub(crate) fn read_raw_targets<F>(
+    targets: &[String],
+    wanted: &[String],
+    mut budget: usize,
+    mut fetch: F,
+) -> Result<Vec<RawTarget>>
+where
+    F: FnMut(&str) -> Result<Option<Vec<u8>>>,
+{
+    let mut raw_targets = Vec::new();
+    for target in wanted.iter().filter(|&target| targets.contains(target)) {
+        if let Some(data) = fetch(target)?
+            && data.len() <= budget
+        {
+            budget -= data.len();
+            raw_targets.push(RawTarget {
+                target: target.clone(),
+                data,
+            });
+        }
+    }
+    Ok(raw_targets)
+}
+
+/// Cut UTF-8 text to at most `max_size` bytes on a character boundary.
+///
+/// Returns `None` if the text is not valid UTF-8 before the cut.
+fn truncate_utf8(mut bytes: Vec<u8>, max_size: usize) -> Option<String> {
+    bytes.truncate(max_size);
+    match String::from_utf8(bytes) {
+        Ok(text) => Some(text),
+        // Only an incomplete character at the very end is ours to drop
+        Err(e) if e.utf8_error().error_len().is_none() => {
+            let valid = e.utf8_error().valid_up_to();
+            let mut bytes = e.into_bytes();
+            bytes.truncate(valid);
+            String::from_utf8(bytes).ok()
+        }
+        Err(_) => None,
+    }
+}
+
+/// Whether the owner marked its data as secret with one of `hints`.
+///
+/// A hint is either a target name, matched when it is offered, or
+/// `target=value`, matched when that target's data equals `value`.
+pub(crate) fn is_concealed<F>(targets: &[String], hints: &[String], mut fetch: F) -> Result<bool>
+where
+    F: FnMut(&str) -> Result<Option<Vec<u8>>>,
+{
+    for hint in hints {
+        let (target, value) = match hint.split_once('=') {
+            Some((target, value)) => (target, Some(value)),
+            None => (hint.as_str(), None),
+        };
+
+        if !targets.iter().any(|t| t == target) {
+            continue;
+        }
+
+        match value {
+            None => return Ok(true),
+            Some(value) => {
+                if let Some(bytes) = fetch(target)?
+                    && String::from_utf8_lossy(&bytes).trim_end_matches(['\0', '\n']) == value
+                {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Decode a text/html transfer; Firefox and some older apps send UTF-16 with a byte order mark
+fn decode_html(bytes: Vec<u8>) -> String {
+    let utf16 = |bytes: &[u8], decode: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| decode([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+
+    match bytes.as_slice() {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_concealed() {
+        let hints = vec!["x-kde-passwordManagerHint=secret".to_string(), "application/x-nspasswd".to_string()];
+        let targets = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let kde = targets(&["UTF8_STRING", "x-kde-passwordManagerHint"]);
+        assert!(is_concealed(&kde, &hints, |_| Ok(Some(b"secret".to_vec()))).unwrap());
+        assert!(!is_concealed(&kde, &hints, |_| Ok(Some(b"public".to_vec()))).unwrap());
+
+        let ns = targets(&["application/x-nspasswd", "UTF8_STRING"]);
+        assert!(is_concealed(&ns, &hints, |_| panic!("presence hints need no transfer")).unwrap());
+
+        assert!(!is_concealed(&targets(&["UTF8_STRING"]), &hints, |_| Ok(None)).unwrap());
+    }
+
+    #[test]
+    fn test_oversized_text() {
+        let targets = vec!["UTF8_STRING".to_string(), "text/html".to_string()];
+        // Transports stop one byte past the limit
+        let fetch = |target: &str| -> Result<Option<Vec<u8>>> {
+            Ok(Some(match target {
+                "UTF8_STRING" => "héllo wörld".as_bytes()[..7].to_vec(),
+                _ => b"<b>x</b>".to_vec(),
+            }))
+        };
+
+        assert_eq!(read_content("test", Some(&targets), 6, OversizedAction::Skip, fetch).unwrap(), None);
+
+        // Nothing is left for the HTML once the text fills the limit
+        let content = read_content("test", Some(&targets), 6, OversizedAction::Truncate, fetch).unwrap();
+        assert_eq!(content, Some(ClipboardContent::from("héllo".to_string())));
+
+        // The cut would split "é", so it is dropped whole
+        let content = read_content("test", Some(&targets), 2, OversizedAction::Truncate, fetch).unwrap();
+        assert_eq!(content.as_ref().and_then(ClipboardContent::text), Some("h"));
+    }
+
+    #[test]
+    fn test_raw_targets_budget() {
+        let targets = vec!["UTF8_STRING".to_string(), "text/x-moz-url".to_string(), "chromium/x-source-url".to_string()];
+        let wanted = vec!["chromium/x-source-url".to_string(), "text/x-moz-url".to_string(), "missing".to_string()];
+        let fetch = |target: &str| -> Result<Option<Vec<u8>>> {
+            Ok(Some(match target {
+                "chromium/x-source-url" => b"https://example.com/".to_vec(),
+                _ => b"https://example.com/page".to_vec(),
+            }))
+        };
+
+        let raw = read_raw_targets(&targets, &wanted, 30, fetch).unwrap();
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0].target, "chromium/x-source-url");
+    }
+
+    #[test]
+    fn test_legacy_text_fallback() {
+        let targets = vec!["UTF8_STRING".to_string(), "STRING".to_string()];
+        // A broken UTF8_STRING falls back to the Latin-1 STRING
+        let fetch = |target: &str| -> Result<Option<Vec<u8>>> {
+            Ok(Some(match target {
+                "UTF8_STRING" => b"caf\xe9".to_vec(),
+                _ => b"caf\xe9s".to_vec(),
+            }))
+        };
+
+        let content = read_content("test", Some(&targets), 100, OversizedAction::Skip, fetch).unwrap();
+        assert_eq!(content.as_ref().and_then(ClipboardContent::text), Some("cafés"));
+
+        // Decoding doubles "é", so the text no longer fits
+        let content = read_content("test", Some(&targets), 5, OversizedAction::Truncate, fetch).unwrap();
+        assert_eq!(content.as_ref().and_then(ClipboardContent::text), Some("café"));
+    }
+}
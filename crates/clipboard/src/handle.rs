@@ -0,0 +1,269 @@
+use std::fmt;
+use std::io::{self, PipeReader, PipeWriter, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use core_lib::config::Config;
+
+use crate::backend::{self, create_listener};
+use crate::event::ClipboardEvent;
+use crate::poll;
+use crate::sync;
+use crate::writer::{ClipboardWriter, Writers};
+
+/// Delay before the first reconnection attempt, doubled after each failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Tells a running listener to return from `start`.
+///
+/// Backed by a pipe, so listeners can wait on it next to their connection.
+#[derive(Debug)]
+pub struct StopSignal {
+    reader: PipeReader,
+    writer: PipeWriter,
+    stopped: AtomicBool,
+}
+
+impl StopSignal {
+    pub fn new() -> io::Result<Self> {
+        let (reader, writer) = io::pipe()?;
+        Ok(Self {
+            reader,
+            writer,
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    pub fn stop(&self) {
+        if !self.stopped.swap(true, Ordering::SeqCst) {
+            // Never drained, so the read end stays readable from now on
+            (&self.writer).write_all(&[0]).ok();
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Sleep for `timeout`, returning `true` early if stopped
+    pub fn wait(&self, timeout: Duration) -> bool {
+        // A deadline too far out to represent means waiting forever
+        poll::wait_readable(self.as_fd(), Instant::now().checked_add(timeout)).ok();
+        self.is_stopped()
+    }
+}
+
+impl AsFd for StopSignal {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.reader.as_fd()
+    }
+}
+
+/// Why a supervised listener is not capturing
+#[derive(Debug)]
+pub enum ListenerError {
+    /// No connection to the display server could be made
+    Connect(anyhow::Error),
+    /// The connection broke while listening, e.g. because the display server restarted
+    Disconnected(anyhow::Error),
+}
+
+impl fmt::Display for ListenerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "failed to connect to the display server: {:#}", e),
+            Self::Disconnected(e) => write!(f, "lost the display server connection: {:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for ListenerError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenerState {
+    Starting,
+    Running,
+    /// Waiting to reconnect after an error
+    Reconnecting,
+    Stopped,
+}
+
+/// Snapshot of a supervised listener's health
+#[derive(Debug, Clone)]
+pub struct ListenerHealth {
+    /// X display the listener watches; `None` for the session's own
+    pub display: Option<String>,
+    pub state: ListenerState,
+    pub last_error: Option<String>,
+    /// Times the listener has been restarted after an error
+    pub restarts: u32,
+}
+
+/// Handle to the listeners running on their own threads, one per display.
+///
+/// Each listener is recreated with exponential backoff whenever it fails,
+/// until `stop` is called.
+pub struct ListenerHandle {
+    stop: Arc<StopSignal>,
+    health: Vec<Arc<Mutex<ListenerHealth>>>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl ListenerHandle {
+    /// Health of each listener
+    pub fn health(&self) -> Vec<ListenerHealth> {
+        self.health.iter().map(|health| health.lock().unwrap().clone()).collect()
+    }
+
+    /// Stop the listeners and wait for their threads to finish
+    pub fn stop(&self) {
+        self.stop.stop();
+        for thread in self.threads.lock().unwrap().drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Run the session's clipboard listeners on supervised threads.
+///
+/// `writers` holds the writer for each watched display. Errors are reported
+/// on the returned channel as they happen; the listeners keep retrying
+/// regardless of whether anyone reads them.
+pub fn spawn_listener<F>(
+    config: Config,
+    writers: Writers,
+    on_change: F,
+) -> Result<(ListenerHandle, mpsc::Receiver<ListenerError>)>
+where
+    F: Fn(ClipboardEvent) + Clone + Send + 'static,
+{
+    let (errors, receiver) = mpsc::channel();
+    let handle = spawn_supervised(config, writers, on_change, move |error| {
+        errors.send(error).ok();
+    })?;
+    Ok((handle, receiver))
+}
+
+/// Run a listener per display on its own thread, recreating it after each failure.
+///
+/// Selections are synced here, so the sync state survives reconnections.
+pub(crate) fn spawn_supervised<F, E>(
+    config: Config,
+    writers: Writers,
+    on_change: F,
+    on_error: E,
+) -> Result<ListenerHandle>
+where
+    F: Fn(ClipboardEvent) + Clone + Send + 'static,
+    E: Fn(ListenerError) + Clone + Send + 'static,
+{
+    let displays = backend::displays(&config);
+    if displays == [None] && !config.capture.displays.is_empty() {
+        eprintln!("Only the X11 backend can watch several displays; watching the session's own");
+    }
+
+    let stop = Arc::new(StopSignal::new()?);
+    let mut health = Vec::new();
+    let mut threads = Vec::new();
+
+    for display in displays {
+        let writer = writers.get(&display).cloned();
+        let on_change = sync::wrap(&config, display.as_deref(), writer.clone(), on_change.clone());
+        let listener_health = Arc::new(Mutex::new(ListenerHealth {
+            display: display.clone(),
+            state: ListenerState::Starting,
+            last_error: None,
+            restarts: 0,
+        }));
+
+        let thread = {
+            let config = config.clone();
+            let on_error = on_error.clone();
+            let stop = stop.clone();
+            let health = listener_health.clone();
+            thread::Builder::new().name("clipboard-listener".to_string()).spawn(move || {
+                supervise(&config, display.as_deref(), writer, on_change, on_error, &stop, &health)
+            })?
+        };
+
+        health.push(listener_health);
+        threads.push(thread);
+    }
+
+    Ok(ListenerHandle {
+        stop,
+        health,
+        threads: Mutex::new(threads),
+    })
+}
+
+fn supervise<F, E>(
+    config: &Config,
+    display: Option<&str>,
+    writer: Option<Arc<dyn ClipboardWriter>>,
+    on_change: F,
+    on_error: E,
+    stop: &StopSignal,
+    health: &Mutex<ListenerHealth>,
+) where
+    F: Fn(ClipboardEvent) + Clone + Send + 'static,
+    E: Fn(ListenerError),
+{
+    let mut backoff = INITIAL_BACKOFF;
+
+    while !stop.is_stopped() {
+        // Say which display failed when watching several
+        let context = |e: anyhow::Error| match display {
+            Some(display) => e.context(format!("display {}", display)),
+            None => e,
+        };
+
+        let error = match create_listener(config, display, writer.clone(), on_change.clone()) {
+            Ok(mut listener) => {
+                health.lock().unwrap().state = ListenerState::Running;
+                backoff = INITIAL_BACKOFF;
+                match listener.start(stop) {
+                    Ok(()) => break,
+                    Err(e) => ListenerError::Disconnected(context(e)),
+                }
+            }
+            Err(e) => ListenerError::Connect(context(e)),
+        };
+
+        {
+            let mut health = health.lock().unwrap();
+            health.state = ListenerState::Reconnecting;
+            health.last_error = Some(error.to_string());
+            health.restarts += 1;
+        }
+        on_error(error);
+
+        if stop.wait(backoff) {
+            break;
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+
+    health.lock().unwrap().state = ListenerState::Stopped;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_signal() {
+        let stop = StopSignal::new().unwrap();
+        assert!(!stop.wait(Duration::from_millis(10)));
+
+        stop.stop();
+        let started = Instant::now();
+        assert!(stop.wait(Duration::from_secs(10)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
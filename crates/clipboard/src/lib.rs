@@ -1,4 +1,23 @@
+pub mod backend;
+mod encoding;
+pub mod event;
+mod formats;
+pub mod handle;
 pub mod listener;
+pub mod mock;
+pub mod null;
+mod poll;
+pub mod stream;
+pub mod sync;
+pub mod wayland;
+pub mod writer;
 pub mod x11;
 
-pub use listener::ClipboardListener;
\ No newline at end of file
+pub use backend::{create_listener, create_writer, create_writers};
+pub use event::ClipboardEvent;
+pub use handle::{spawn_listener, ListenerError, ListenerHandle, ListenerHealth, ListenerState, StopSignal};
+pub use listener::ClipboardListener;
+pub use mock::MockClipboard;
+pub use stream::{listen, ClipboardEventStream};
+pub use sync::SelectionSync;
+pub use writer::{ClipboardWriter, Writers};
@@ -1,3 +1,6 @@
+use crate::handle::StopSignal;
+
 pub trait ClipboardListener {
-    fn start(&mut self) -> anyhow::Result<()>;
+    /// Capture clipboard changes until `stop` is signalled or the connection fails
+    fn start(&mut self, stop: &StopSignal) -> anyhow::Result<()>;
 }
\ No newline at end of file
@@ -0,0 +1,179 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::os::fd::AsFd;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use core_lib::buffer::{ClipboardContent, Selection};
+
+use crate::event::ClipboardEvent;
+use crate::handle::StopSignal;
+use crate::listener::ClipboardListener;
+use crate::poll;
+use crate::writer::ClipboardWriter;
+
+/// In-memory clipboard for tests and headless runs.
+///
+/// Events injected with `inject` or `copy` are delivered by its listeners,
+/// and content set through its writer is recorded for inspection.
+#[derive(Clone)]
+pub struct MockClipboard {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    events: Mutex<VecDeque<ClipboardEvent>>,
+    /// Written to on every injection to wake the listener
+    wake_reader: PipeReader,
+    wake_writer: PipeWriter,
+    written: Mutex<Vec<(Selection, ClipboardContent)>>,
+    current: Mutex<HashMap<Selection, ClipboardContent>>,
+}
+
+impl MockClipboard {
+    pub fn new() -> io::Result<Self> {
+        let (wake_reader, wake_writer) = io::pipe()?;
+        Ok(Self {
+            shared: Arc::new(Shared {
+                events: Mutex::new(VecDeque::new()),
+                wake_reader,
+                wake_writer,
+                written: Mutex::new(Vec::new()),
+                current: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Process-wide instance used when the `mock` backend is selected in the config
+    pub fn global() -> &'static MockClipboard {
+        static GLOBAL: OnceLock<MockClipboard> = OnceLock::new();
+        GLOBAL.get_or_init(|| Self::new().expect("Failed to create mock clipboard"))
+    }
+
+    /// Deliver `event` to the listener, as if an application changed the selection
+    pub fn inject(&self, event: ClipboardEvent) {
+        self.shared.current.lock().unwrap().insert(event.selection, event.content.clone());
+        self.shared.events.lock().unwrap().push_back(event);
+        (&self.shared.wake_writer).write_all(&[0]).ok();
+    }
+
+    /// Simulate an application copying `content` to `selection`
+    pub fn copy(&self, selection: Selection, content: impl Into<ClipboardContent>) {
+        self.inject(ClipboardEvent {
+            content: content.into(),
+            mime_types: Vec::new(),
+            raw_targets: Vec::new(),
+            selection,
+            source: None,
+            display: None,
+            sensitive: false,
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    /// Everything set through the writer so far, oldest first
+    pub fn written(&self) -> Vec<(Selection, ClipboardContent)> {
+        self.shared.written.lock().unwrap().clone()
+    }
+
+    /// Content currently held by `selection`
+    pub fn current(&self, selection: Selection) -> Option<ClipboardContent> {
+        self.shared.current.lock().unwrap().get(&selection).cloned()
+    }
+
+    pub fn listener<F>(&self, on_change: F) -> MockClipboardListener<F>
+    where
+        F: Fn(ClipboardEvent) + Send + 'static,
+    {
+        MockClipboardListener {
+            clipboard: self.clone(),
+            on_change,
+        }
+    }
+
+    pub fn writer(&self) -> MockClipboardWriter {
+        MockClipboardWriter {
+            clipboard: self.clone(),
+        }
+    }
+}
+
+/// Delivers events injected into a `MockClipboard`
+pub struct MockClipboardListener<F>
+where
+    F: Fn(ClipboardEvent) + Send + 'static,
+{
+    clipboard: MockClipboard,
+    on_change: F,
+}
+
+impl<F> ClipboardListener for MockClipboardListener<F>
+where
+    F: Fn(ClipboardEvent) + Send + 'static,
+{
+    fn start(&mut self, stop: &StopSignal) -> Result<()> {
+        let shared = &self.clipboard.shared;
+        loop {
+            let event = shared.events.lock().unwrap().pop_front();
+            match event {
+                Some(event) => (self.on_change)(event),
+                None => {
+                    poll::wait_readable_or_stopped(shared.wake_reader.as_fd(), None, Some(stop))?;
+                    if stop.is_stopped() {
+                        return Ok(());
+                    }
+                    (&shared.wake_reader).read_exact(&mut [0])?;
+                }
+            }
+        }
+    }
+}
+
+/// Records content set through it in a `MockClipboard`
+pub struct MockClipboardWriter {
+    clipboard: MockClipboard,
+}
+
+impl ClipboardWriter for MockClipboardWriter {
+    fn set(&self, selection: Selection, content: &ClipboardContent) -> Result<()> {
+        println!("Mock clipboard: {:?} set to {}", selection, content.summary());
+
+        let shared = &self.clipboard.shared;
+        shared.current.lock().unwrap().insert(selection, content.clone());
+        shared.written.lock().unwrap().push((selection, content.clone()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_roundtrip() {
+        let clipboard = MockClipboard::new().unwrap();
+        let stop = Arc::new(StopSignal::new().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        let mut listener = clipboard.listener(move |event| {
+            sender.send(event).ok();
+        });
+        let listener_stop = stop.clone();
+        let thread = thread::spawn(move || listener.start(&listener_stop).unwrap());
+
+        clipboard.copy(Selection::Clipboard, "hello".to_string());
+        let event = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.content.text(), Some("hello"));
+
+        clipboard.writer().set(Selection::Primary, &event.content).unwrap();
+        assert_eq!(clipboard.written(), vec![(Selection::Primary, event.content.clone())]);
+        assert_eq!(clipboard.current(Selection::Primary), Some(event.content));
+
+        stop.stop();
+        thread.join().unwrap();
+    }
+}
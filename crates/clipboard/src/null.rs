@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use core_lib::buffer::{ClipboardContent, Selection};
+
+use crate::handle::StopSignal;
+use crate::listener::ClipboardListener;
+use crate::writer::ClipboardWriter;
+
+/// Listener for sessions without a supported clipboard; captures nothing
+#[derive(Debug, Default)]
+pub struct NullClipboardListener;
+
+impl NullClipboardListener {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl ClipboardListener for NullClipboardListener {
+    fn start(&mut self, stop: &StopSignal) -> Result<()> {
+        while !stop.wait(Duration::MAX) {}
+        Ok(())
+    }
+}
+
+/// Writer for sessions without a supported clipboard; every write fails
+#[derive(Debug, Default)]
+pub struct NullClipboardWriter;
+
+impl ClipboardWriter for NullClipboardWriter {
+    fn set(&self, _selection: Selection, _content: &ClipboardContent) -> Result<()> {
+        bail!("No clipboard backend available")
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::time::Instant;
+
+use crate::handle::StopSignal;
+
+/// Wait until `fd` is readable or `deadline` passes.
+///
+/// Returns `false` on timeout. Without a deadline this waits indefinitely.
+pub(crate) fn wait_readable(fd: BorrowedFd<'_>, deadline: Option<Instant>) -> io::Result<bool> {
+    wait_readable_or_stopped(fd, deadline, None)
+}
+
+/// Like `wait_readable`, but also returns `false` as soon as `stop` is signalled
+pub(crate) fn wait_readable_or_stopped(
+    fd: BorrowedFd<'_>,
+    deadline: Option<Instant>,
+    stop: Option<&StopSignal>,
+) -> io::Result<bool> {
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(false);
+                }
+                (deadline - now).as_millis().clamp(1, i32::MAX as u128) as i32
+            }
+            None => -1,
+        };
+
+        let pollfd = |fd: i32| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // A negative fd is ignored by poll, so the stop slot is inert without a signal
+        let mut pollfds = [
+            pollfd(fd.as_raw_fd()),
+            pollfd(stop.map_or(-1, |stop| stop.as_fd().as_raw_fd())),
+        ];
+
+        // SAFETY: `pollfds` is a valid array of pollfds that lives for the duration of the call
+        match unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) } {
+            n if n > 0 => return Ok(pollfds[1].revents == 0),
+            0 => return Ok(false),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::Result;
+use core_lib::config::Config;
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+use crate::event::ClipboardEvent;
+use crate::handle::{self, ListenerError, ListenerHandle};
+use crate::writer::Writers;
+
+/// Async stream of clipboard events from the supervised listeners.
+///
+/// Listener errors are yielded between events. The listeners keep
+/// reconnecting after them, so the stream only ends once they are stopped.
+pub struct ClipboardEventStream {
+    receiver: mpsc::UnboundedReceiver<Result<ClipboardEvent, ListenerError>>,
+    handle: Arc<ListenerHandle>,
+}
+
+impl ClipboardEventStream {
+    /// Next event or error, or `None` once the listeners have stopped
+    pub async fn next(&mut self) -> Option<Result<ClipboardEvent, ListenerError>> {
+        self.receiver.recv().await
+    }
+
+    /// Handle to stop the listeners or check their health
+    pub fn handle(&self) -> Arc<ListenerHandle> {
+        self.handle.clone()
+    }
+}
+
+impl Stream for ClipboardEventStream {
+    type Item = Result<ClipboardEvent, ListenerError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Start a clipboard listener for every watched display and stream their events
+pub fn listen(config: Config, writers: Writers) -> Result<ClipboardEventStream> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let errors = sender.clone();
+
+    let handle = handle::spawn_supervised(
+        config,
+        writers,
+        move |event| {
+            sender.send(Ok(event)).ok();
+        },
+        move |error| {
+            errors.send(Err(error)).ok();
+        },
+    )?;
+
+    Ok(ClipboardEventStream {
+        receiver,
+        handle: Arc::new(handle),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_lib::config::Backend;
+
+    #[tokio::test]
+    async fn test_stream_ends_when_stopped() {
+        let mut config = Config::default();
+        config.capture.backend = Backend::Null;
+
+        let mut events = listen(config, Writers::new()).unwrap();
+        let handle = events.handle();
+        tokio::task::spawn_blocking(move || handle.stop()).await.unwrap();
+
+        assert!(events.next().await.is_none());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use core_lib::buffer::{ClipboardContent, Selection};
+use core_lib::config::{CaptureConfig, Config, SyncMode};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::Window;
+use x11rb::rust_connection::RustConnection;
+
+use crate::event::ClipboardEvent;
+use crate::writer::ClipboardWriter;
+use crate::x11;
+
+/// Copies changes of one selection to the other, as set by `capture.sync`
+pub struct SelectionSync {
+    mode: SyncMode,
+    writer: Arc<dyn ClipboardWriter>,
+    /// Connection used to check the mouse buttons; only X11 exposes them to clients
+    pointer: Option<(RustConnection, Window)>,
+    /// Last content seen on each selection
+    seen: HashMap<Selection, ClipboardContent>,
+    /// Content we put on each selection whose change notification is still to come
+    written: HashMap<Selection, ClipboardContent>,
+}
+
+impl SelectionSync {
+    /// Sync through `writer`, checking the pointer on X `display`; returns `None` when syncing is off
+    pub fn new(config: &CaptureConfig, display: Option<&str>, writer: Arc<dyn ClipboardWriter>) -> Option<Self> {
+        if config.sync == SyncMode::Off {
+            return None;
+        }
+
+        let pointer = if config.sync_skip_while_held && config.watches_primary() {
+            RustConnection::connect(display).ok().map(|(connection, screen)| {
+                let root = connection.setup().roots[screen].root;
+                (connection, root)
+            })
+        } else {
+            None
+        };
+
+        Some(Self {
+            mode: config.sync,
+            writer,
+            pointer,
+            seen: HashMap::new(),
+            written: HashMap::new(),
+        })
+    }
+
+    /// Copy `event` to the other selection if the mode asks for it
+    pub fn handle(&mut self, event: &ClipboardEvent) {
+        let previous = self.seen.insert(event.selection, event.content.clone());
+
+        // Our own write coming back; syncing it again would bounce between the selections
+        if self.written.remove(&event.selection).is_some_and(|written| written == event.content) {
+            return;
+        }
+
+        let Some(target) = self.target(event.selection) else {
+            return;
+        };
+        if event.sensitive || previous.as_ref() == Some(&event.content) {
+            return;
+        }
+        if self.seen.get(&target) == Some(&event.content) {
+            return;
+        }
+        if event.selection == Selection::Primary && self.button_held() {
+            return;
+        }
+
+        if let Err(e) = self.writer.set(target, &event.content) {
+            eprintln!("Failed to sync {:?} to {:?}: {:#}", event.selection, target, e);
+            return;
+        }
+        self.seen.insert(target, event.content.clone());
+        self.written.insert(target, event.content.clone());
+    }
+
+    fn target(&self, selection: Selection) -> Option<Selection> {
+        match (self.mode, selection) {
+            (SyncMode::PrimaryToClipboard | SyncMode::Both, Selection::Primary) => Some(Selection::Clipboard),
+            (SyncMode::ClipboardToPrimary | SyncMode::Both, Selection::Clipboard) => Some(Selection::Primary),
+            _ => None,
+        }
+    }
+
+    fn button_held(&self) -> bool {
+        self.pointer
+            .as_ref()
+            .is_some_and(|(connection, root)| x11::pointer_button_held(connection, *root).unwrap_or(false))
+    }
+}
+
+/// Wrap a listener callback so selections are synced before changes are passed on.
+///
+/// PRIMARY may be watched only for syncing, so its changes are passed on
+/// only when `capture.primary` is set.
+pub(crate) fn wrap<F>(
+    config: &Config,
+    display: Option<&str>,
+    writer: Option<Arc<dyn ClipboardWriter>>,
+    on_change: F,
+) -> impl Fn(ClipboardEvent) + Clone + Send + 'static
+where
+    F: Fn(ClipboardEvent) + Clone + Send + 'static,
+{
+    let sync = match writer {
+        Some(writer) => SelectionSync::new(&config.capture, display, writer),
+        None => {
+            if config.capture.sync != SyncMode::Off {
+                eprintln!("Selection sync needs a clipboard writer; it is disabled");
+            }
+            None
+        }
+    };
+    let sync = Arc::new(sync.map(Mutex::new));
+    let capture_primary = config.capture.primary;
+
+    move |event: ClipboardEvent| {
+        if let Some(sync) = sync.as_ref() {
+            sync.lock().unwrap().handle(&event);
+        }
+        if event.selection == Selection::Clipboard || capture_primary {
+            on_change(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockClipboard;
+    use std::time::SystemTime;
+
+    fn event(selection: Selection, text: &str) -> ClipboardEvent {
+        ClipboardEvent {
+            content: text.to_string().into(),
+            mime_types: Vec::new(),
+            raw_targets: Vec::new(),
+            selection,
+            source: None,
+            display: None,
+            sensitive: false,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    #[test]
+    fn test_sync_both_without_loops() {
+        let clipboard = MockClipboard::new().unwrap();
+        let config = CaptureConfig {
+            sync: SyncMode::Both,
+            sync_skip_while_held: false,
+            ..CaptureConfig::default()
+        };
+        let mut sync = SelectionSync::new(&config, None, Arc::new(clipboard.writer())).unwrap();
+
+        sync.handle(&event(Selection::Primary, "hello"));
+        // The notification for our own write must not be synced back
+        sync.handle(&event(Selection::Clipboard, "hello"));
+        sync.handle(&event(Selection::Clipboard, "world"));
+        sync.handle(&event(Selection::Primary, "world"));
+
+        let written: Vec<_> = clipboard
+            .written()
+            .into_iter()
+            .map(|(selection, content)| (selection, content.text().unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            written,
+            vec![
+                (Selection::Clipboard, "hello".to_string()),
+                (Selection::Primary, "world".to_string()),
+            ]
+        );
+    }
+}
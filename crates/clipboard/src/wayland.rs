@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, OwnedFd};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use core_lib::buffer::{ClipboardContent, Selection};
+use core_lib::config::{ConcealedAction, Config, OversizedAction, PrivacyConfig};
+use wayland_client::backend::{ObjectId, WaylandError};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_registry::WlRegistry, wl_seat::WlSeat};
+use wayland_client::{delegate_noop, event_created_child, Connection, Dispatch, EventQueue, Proxy, QueueHandle};
+use wayland_protocols::ext::data_control::v1::client::{
+    ext_data_control_device_v1::{self, ExtDataControlDeviceV1},
+    ext_data_control_manager_v1::ExtDataControlManagerV1,
+    ext_data_control_offer_v1::{self, ExtDataControlOfferV1},
+    ext_data_control_source_v1::{self, ExtDataControlSourceV1},
+};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+
+use crate::event::ClipboardEvent;
+use crate::formats;
+use crate::handle::StopSignal;
+use crate::listener::ClipboardListener;
+use crate::poll;
+use crate::writer::ClipboardWriter;
+
+/// How long the source client may stay silent while sending us data
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Wayland clipboard listener built on the data-control protocols.
+///
+/// Prefers `ext-data-control-v1` and falls back to
+/// `wlr-data-control-unstable-v1` on compositors that only offer that.
+pub struct WaylandClipboardListener<F>
+where
+    F: Fn(ClipboardEvent) + Send + 'static,
+{
+    connection: Connection,
+    queue: EventQueue<State>,
+    state: State,
+    on_change: F,
+    capture_primary: bool,
+    primary_settle: Duration,
+    privacy: PrivacyConfig,
+    max_entry_size: usize,
+    on_oversized: OversizedAction,
+    /// Targets kept verbatim with each entry, and their total size limit
+    raw_targets: Vec<String>,
+    raw_targets_budget: usize,
+    last_values: HashMap<Selection, ClipboardContent>,
+    /// Offers currently holding each selection
+    offers: HashMap<Selection, DataOffer>,
+    /// PRIMARY change waiting for the selection to settle
+    pending_primary: Option<Instant>,
+}
+
+/// Events collected while dispatching, handled by the listener afterwards
+#[derive(Default)]
+struct State {
+    /// MIME types announced for each live offer
+    mime_types: HashMap<ObjectId, Vec<String>>,
+    changes: Vec<(Selection, Option<DataOffer>)>,
+    finished: bool,
+}
+
+/// Data offer from either data-control protocol
+#[derive(Clone)]
+enum DataOffer {
+    Ext(ExtDataControlOfferV1),
+    Wlr(ZwlrDataControlOfferV1),
+}
+
+impl DataOffer {
+    fn id(&self) -> ObjectId {
+        match self {
+            Self::Ext(offer) => offer.id(),
+            Self::Wlr(offer) => offer.id(),
+        }
+    }
+
+    fn receive(&self, mime_type: &str, fd: std::os::fd::BorrowedFd<'_>) {
+        match self {
+            Self::Ext(offer) => offer.receive(mime_type.to_string(), fd),
+            Self::Wlr(offer) => offer.receive(mime_type.to_string(), fd),
+        }
+    }
+
+    fn destroy(&self) {
+        match self {
+            Self::Ext(offer) => offer.destroy(),
+            Self::Wlr(offer) => offer.destroy(),
+        }
+    }
+}
+
+impl<F> WaylandClipboardListener<F>
+where
+    F: Fn(ClipboardEvent) + Send + 'static,
+{
+    pub fn new(config: &Config, on_change: F) -> Result<Self> {
+        let connection = Connection::connect_to_env()
+            .context("Failed to connect to the Wayland compositor")?;
+        let (globals, queue) = registry_queue_init::<State>(&connection)?;
+        let qh = queue.handle();
+
+        let seat: WlSeat = globals.bind(&qh, 1..=1, ()).context("Compositor has no seat")?;
+
+        let mut capture_primary = config.capture.watches_primary();
+        if let Ok(manager) = globals.bind::<ExtDataControlManagerV1, _, _>(&qh, 1..=1, ()) {
+            manager.get_data_device(&seat, &qh, ());
+        } else {
+            let manager: ZwlrDataControlManagerV1 = globals.bind(&qh, 1..=2, ()).context(
+                "Compositor supports neither ext-data-control-v1 nor wlr-data-control-unstable-v1",
+            )?;
+
+            // PRIMARY selection support arrived in version 2
+            if capture_primary && manager.version() < 2 {
+                eprintln!("Compositor does not expose PRIMARY to data-control clients; capturing CLIPBOARD only");
+                capture_primary = false;
+            }
+            manager.get_data_device(&seat, &qh, ());
+        }
+
+        Ok(Self {
+            connection,
+            queue,
+            state: State::default(),
+            on_change,
+            capture_primary,
+            primary_settle: Duration::from_millis(config.capture.primary_settle_ms),
+            privacy: config.privacy.clone(),
+            max_entry_size: config.max_entry_size,
+            on_oversized: config.on_oversized,
+            raw_targets: config.capture.raw_targets.clone(),
+            raw_targets_budget: config.capture.raw_targets_budget,
+            last_values: HashMap::new(),
+            offers: HashMap::new(),
+            pending_primary: None,
+        })
+    }
+
+    /// Read and dispatch compositor events, giving up once `deadline` has passed or `stop` is signalled
+    fn dispatch(&mut self, deadline: Option<Instant>, stop: &StopSignal) -> Result<()> {
+        self.queue.dispatch_pending(&mut self.state)?;
+        if !self.state.changes.is_empty() {
+            return Ok(());
+        }
+
+        self.connection.flush()?;
+        if let Some(guard) = self.queue.prepare_read()
+            && poll::wait_readable_or_stopped(guard.connection_fd(), deadline, Some(stop))?
+        {
+            match guard.read() {
+                Ok(_) => {}
+                Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        self.queue.dispatch_pending(&mut self.state)?;
+        Ok(())
+    }
+
+    /// Remember the offer now holding `selection`, releasing the one it replaces
+    fn set_offer(&mut self, selection: Selection, offer: Option<DataOffer>) {
+        let previous = match offer {
+            Some(offer) => self.offers.insert(selection, offer),
+            None => self.offers.remove(&selection),
+        };
+
+        if let Some(previous) = previous {
+            self.state.mime_types.remove(&previous.id());
+            previous.destroy();
+        }
+    }
+
+    /// Transfer the offer's data in `mime_type`, or `None` if the source stops answering.
+    ///
+    /// Reads at most one byte past `max_entry_size`, so oversized data is never held in full.
+    fn receive(&self, offer: &DataOffer, mime_type: &str) -> Result<Option<Vec<u8>>> {
+        let limit = self.max_entry_size.saturating_add(1);
+        let (mut reader, writer) = io::pipe()?;
+        offer.receive(mime_type, writer.as_fd());
+        self.connection.flush()?;
+
+        // Close our copy of the write end so we see EOF once the source is done
+        drop(writer);
+
+        let mut data = Vec::new();
+        let mut chunk = [0u8; 8192];
+        loop {
+            if !poll::wait_readable(reader.as_fd(), Some(Instant::now() + RECEIVE_TIMEOUT))? {
+                return Ok(None);
+            }
+
+            match reader.read(&mut chunk)? {
+                0 => return Ok(Some(data)),
+                n => data.extend_from_slice(&chunk[..n]),
+            }
+
+            // Closing our end tells the source to stop sending
+            if data.len() >= limit {
+                data.truncate(limit);
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    /// Read the content currently held by `selection` and report it if it changed
+    fn capture(&mut self, selection: Selection) -> Result<()> {
+        let Some(offer) = self.offers.get(&selection).cloned() else {
+            return Ok(());
+        };
+        let mime_types = self.state.mime_types.get(&offer.id()).cloned().unwrap_or_default();
+
+        let sensitive = formats::is_concealed(&mime_types, &self.privacy.concealment_hints, |mime_type| {
+            self.receive(&offer, mime_type)
+        })?;
+        if sensitive && self.privacy.on_concealed == ConcealedAction::Skip {
+            // Forget the previous value so copying it again is recorded
+            self.last_values.remove(&selection);
+            return Ok(());
+        }
+
+        let (max_size, on_oversized) = (self.max_entry_size, self.on_oversized);
+        let content = formats::read_content("Wayland client", Some(&mime_types), max_size, on_oversized, |mime_type| {
+            self.receive(&offer, mime_type)
+        })?;
+
+        if let Some(content) = content
+            && self.last_values.get(&selection) != Some(&content)
+        {
+            self.last_values.insert(selection, content.clone());
+
+            let (wanted, budget) = (self.raw_targets.clone(), self.raw_targets_budget);
+            // Raw data could carry the secret itself
+            let raw_targets = if sensitive {
+                Vec::new()
+            } else {
+                formats::read_raw_targets(&mime_types, &wanted, budget, |mime_type| self.receive(&offer, mime_type))?
+            };
+
+            // Data-control offers don't identify the client behind them
+            (self.on_change)(ClipboardEvent {
+                content,
+                mime_types,
+                raw_targets,
+                selection,
+                source: None,
+                display: None,
+                sensitive,
+                timestamp: SystemTime::now(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> ClipboardListener for WaylandClipboardListener<F>
+where
+    F: Fn(ClipboardEvent) + Send + 'static,
+{
+    fn start(&mut self, stop: &StopSignal) -> Result<()> {
+        // The compositor announces the current selections right after binding,
+        // so whatever is already on the clipboard is picked up by the first dispatch
+        loop {
+            self.dispatch(self.pending_primary, stop)?;
+            if stop.is_stopped() {
+                return Ok(());
+            }
+
+            if self.state.finished {
+                anyhow::bail!("Data-control device was destroyed by the compositor");
+            }
+
+            for (selection, offer) in std::mem::take(&mut self.state.changes) {
+                let has_offer = offer.is_some();
+                self.set_offer(selection, offer);
+
+                match selection {
+                    Selection::Clipboard if has_offer => self.capture(Selection::Clipboard)?,
+                    Selection::Primary if has_offer && self.capture_primary => {
+                        // Wait for the selection to settle before reading it
+                        self.pending_primary = Some(Instant::now() + self.primary_settle);
+                    }
+                    _ => {}
+                }
+            }
+
+            if self.pending_primary.is_some_and(|at| Instant::now() >= at) {
+                self.pending_primary = None;
+                self.capture(Selection::Primary)?;
+            }
+        }
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ExtDataControlManagerV1);
+delegate_noop!(State: ZwlrDataControlManagerV1);
+
+/// Both data-control protocols share the same shape; implement them once
+macro_rules! impl_data_control_dispatch {
+    ($variant:ident, $device:ty, $device_mod:ident, $offer:ty, $offer_mod:ident) => {
+        impl Dispatch<$device, ()> for State {
+            fn event(
+                state: &mut Self,
+                _: &$device,
+                event: $device_mod::Event,
+                _: &(),
+                _: &Connection,
+                _: &QueueHandle<Self>,
+            ) {
+                match event {
+                    $device_mod::Event::DataOffer { id } => {
+                        state.mime_types.insert(id.id(), Vec::new());
+                    }
+                    $device_mod::Event::Selection { id } => {
+                        state.changes.push((Selection::Clipboard, id.map(DataOffer::$variant)));
+                    }
+                    $device_mod::Event::PrimarySelection { id } => {
+                        state.changes.push((Selection::Primary, id.map(DataOffer::$variant)));
+                    }
+                    $device_mod::Event::Finished => state.finished = true,
+                    _ => {}
+                }
+            }
+
+            event_created_child!(State, $device, [
+                $device_mod::EVT_DATA_OFFER_OPCODE => ($offer, ()),
+            ]);
+        }
+
+        impl Dispatch<$offer, ()> for State {
+            fn event(
+                state: &mut Self,
+                offer: &$offer,
+                event: $offer_mod::Event,
+                _: &(),
+                _: &Connection,
+                _: &QueueHandle<Self>,
+            ) {
+                if let $offer_mod::Event::Offer { mime_type } = event {
+                    state.mime_types.entry(offer.id()).or_default().push(mime_type);
+                }
+            }
+        }
+    };
+}
+
+impl_data_control_dispatch!(
+    Ext,
+    ExtDataControlDeviceV1,
+    ext_data_control_device_v1,
+    ExtDataControlOfferV1,
+    ext_data_control_offer_v1
+);
+impl_data_control_dispatch!(
+    Wlr,
+    ZwlrDataControlDeviceV1,
+    zwlr_data_control_device_v1,
+    ZwlrDataControlOfferV1,
+    zwlr_data_control_offer_v1
+);
+
+/// Data offered by each live source, by source object
+type Sources = Arc<Mutex<HashMap<ObjectId, Vec<(String, Vec<u8>)>>>>;
+
+/// Serves clipboard content to Wayland clients through data-control sources.
+///
+/// A background thread dispatches send requests until the compositor
+/// cancels the source because another client set the selection.
+pub struct WaylandClipboardWriter {
+    connection: Connection,
+    qh: QueueHandle<WriterState>,
+    data_control: DataControl,
+    sources: Sources,
+}
+
+/// Bound data-control manager and device, from whichever protocol is available
+enum DataControl {
+    Ext(ExtDataControlManagerV1, ExtDataControlDeviceV1),
+    Wlr(ZwlrDataControlManagerV1, ZwlrDataControlDeviceV1),
+}
+
+struct WriterState {
+    sources: Sources,
+}
+
+impl WaylandClipboardWriter {
+    pub fn new() -> Result<Self> {
+        let connection = Connection::connect_to_env()
+            .context("Failed to connect to the Wayland compositor")?;
+        let (globals, mut queue) = registry_queue_init::<WriterState>(&connection)?;
+        let qh = queue.handle();
+
+        let seat: WlSeat = globals.bind(&qh, 1..=1, ()).context("Compositor has no seat")?;
+
+        let data_control = if let Ok(manager) = globals.bind::<ExtDataControlManagerV1, _, _>(&qh, 1..=1, ()) {
+            let device = manager.get_data_device(&seat, &qh, ());
+            DataControl::Ext(manager, device)
+        } else {
+            let manager: ZwlrDataControlManagerV1 = globals.bind(&qh, 1..=2, ()).context(
+                "Compositor supports neither ext-data-control-v1 nor wlr-data-control-unstable-v1",
+            )?;
+            let device = manager.get_data_device(&seat, &qh, ());
+            DataControl::Wlr(manager, device)
+        };
+
+        let sources = Sources::default();
+        let mut state = WriterState {
+            sources: sources.clone(),
+        };
+        thread::Builder::new()
+            .name("wayland-clipboard-writer".to_string())
+            .spawn(move || {
+                loop {
+                    if let Err(e) = queue.blocking_dispatch(&mut state) {
+                        eprintln!("Wayland clipboard writer stopped: {}", e);
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            connection,
+            qh,
+            data_control,
+            sources,
+        })
+    }
+}
+
+impl ClipboardWriter for WaylandClipboardWriter {
+    fn set(&self, selection: Selection, content: &ClipboardContent) -> Result<()> {
+        let targets = formats::offered_targets(content);
+
+        macro_rules! set_source {
+            ($manager:expr, $device:expr) => {{
+                let source = $manager.create_data_source(&self.qh, ());
+                for (mime_type, _) in &targets {
+                    source.offer(mime_type.clone());
+                }
+                self.sources.lock().unwrap().insert(source.id(), targets);
+
+                match selection {
+                    Selection::Clipboard => $device.set_selection(Some(&source)),
+                    Selection::Primary => $device.set_primary_selection(Some(&source)),
+                }
+            }};
+        }
+
+        match &self.data_control {
+            DataControl::Ext(manager, device) => set_source!(manager, device),
+            DataControl::Wlr(manager, device) => {
+                // PRIMARY selection support arrived in version 2
+                if selection == Selection::Primary && manager.version() < 2 {
+                    bail!("Compositor does not let data-control clients set PRIMARY");
+                }
+                set_source!(manager, device)
+            }
+        }
+
+        self.connection.flush()?;
+        Ok(())
+    }
+}
+
+impl WriterState {
+    /// Write the source's data in `mime_type` to `fd` without blocking the dispatch thread
+    fn send(&self, source: ObjectId, mime_type: &str, fd: OwnedFd) {
+        let data = self.sources.lock().unwrap().get(&source).and_then(|targets| {
+            targets
+                .iter()
+                .find(|(target, _)| target == mime_type)
+                .map(|(_, data)| data.clone())
+        });
+
+        if let Some(data) = data {
+            thread::spawn(move || {
+                // The paster may close its end early; that is not our problem
+                File::from(fd).write_all(&data).ok();
+            });
+        }
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for WriterState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(WriterState: ignore WlSeat);
+delegate_noop!(WriterState: ExtDataControlManagerV1);
+delegate_noop!(WriterState: ZwlrDataControlManagerV1);
+delegate_noop!(WriterState: ignore ExtDataControlOfferV1);
+delegate_noop!(WriterState: ignore ZwlrDataControlOfferV1);
+
+/// Writer counterpart of `impl_data_control_dispatch`
+macro_rules! impl_data_control_writer_dispatch {
+    ($device:ty, $device_mod:ident, $offer:ty, $source:ty, $source_mod:ident) => {
+        impl Dispatch<$device, ()> for WriterState {
+            fn event(
+                _: &mut Self,
+                _: &$device,
+                event: $device_mod::Event,
+                _: &(),
+                _: &Connection,
+                _: &QueueHandle<Self>,
+            ) {
+                // The writer never reads offers, so release them right away
+                match event {
+                    $device_mod::Event::Selection { id: Some(offer) }
+                    | $device_mod::Event::PrimarySelection { id: Some(offer) } => offer.destroy(),
+                    _ => {}
+                }
+            }
+
+            event_created_child!(WriterState, $device, [
+                $device_mod::EVT_DATA_OFFER_OPCODE => ($offer, ()),
+            ]);
+        }
+
+        impl Dispatch<$source, ()> for WriterState {
+            fn event(
+                state: &mut Self,
+                source: &$source,
+                event: $source_mod::Event,
+                _: &(),
+                _: &Connection,
+                _: &QueueHandle<Self>,
+            ) {
+                match event {
+                    $source_mod::Event::Send { mime_type, fd } => state.send(source.id(), &mime_type, fd),
+                    $source_mod::Event::Cancelled => {
+                        state.sources.lock().unwrap().remove(&source.id());
+                        source.destroy();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    };
+}
+
+impl_data_control_writer_dispatch!(
+    ExtDataControlDeviceV1,
+    ext_data_control_device_v1,
+    ExtDataControlOfferV1,
+    ExtDataControlSourceV1,
+    ext_data_control_source_v1
+);
+impl_data_control_writer_dispatch!(
+    ZwlrDataControlDeviceV1,
+    zwlr_data_control_device_v1,
+    ZwlrDataControlOfferV1,
+    ZwlrDataControlSourceV1,
+    zwlr_data_control_source_v1
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::sync::mpsc;
+
+    /// Run against a compositor with data-control, e.g. via `./test-wayland.sh`
+    #[test]
+    #[ignore = "needs a Wayland compositor with data-control and wl-copy"]
+    fn test_captures_wl_copy() {
+        let (sender, receiver) = mpsc::channel();
+        let config = Config::default();
+
+        std::thread::spawn(move || {
+            let mut listener = WaylandClipboardListener::new(&config, move |event| {
+                sender.send(event).ok();
+            })
+            .unwrap();
+            listener.start(&StopSignal::new().unwrap()).unwrap();
+        });
+
+        let status = Command::new("wl-copy").arg("hello from wayland").status().unwrap();
+        assert!(status.success());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let event = receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .expect("no clipboard event received");
+
+            if event.content.text() == Some("hello from wayland") {
+                assert_eq!(event.selection, Selection::Clipboard);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use core_lib::buffer::{ClipboardContent, Selection};
+
+/// Writers by X display; `None` is the session's own display
+pub type Writers = HashMap<Option<String>, Arc<dyn ClipboardWriter>>;
+
+/// Takes ownership of a selection and serves content to applications that paste it
+pub trait ClipboardWriter: Send + Sync {
+    /// Own `selection` and offer `content` in every format we have for it
+    fn set(&self, selection: Selection, content: &ClipboardContent) -> anyhow::Result<()>;
+}
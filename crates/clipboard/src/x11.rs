@@ -1,53 +1,854 @@
-use std::{thread, time::Duration};
+use std::collections::{HashMap, VecDeque};
+use std::os::fd::AsFd;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::Result;
-use x11_clipboard::Clipboard;
+use anyhow::{bail, Result};
+use core_lib::buffer::{ClipboardContent, Selection, SourceApp};
+use core_lib::config::{ConcealedAction, Config, OversizedAction, PrivacyConfig};
+use x11_clipboard::{Context, RustConnection};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::ReplyError;
+use x11rb::protocol::Event;
+use x11rb::protocol::xfixes::{self, ConnectionExt as _, SelectionEventMask};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConnectionExt as _, EventMask, GetPropertyReply,
+    KeyButMask, Property, PropMode, SelectionNotifyEvent, SelectionRequestEvent, Timestamp, Window,
+    SELECTION_NOTIFY_EVENT,
+};
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::CURRENT_TIME;
 
+use crate::event::ClipboardEvent;
+use crate::formats;
+use crate::handle::StopSignal;
 use crate::listener::ClipboardListener;
+use crate::poll;
+use crate::writer::ClipboardWriter;
 
+/// How long the selection owner gets to answer a conversion request
+const CONVERT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long the owner may take to send each chunk of an incremental transfer
+const INCR_CHUNK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Largest chunk we send in one property when serving a selection
+const INCR_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Whether a mouse button is currently held, e.g. while dragging out a selection
+pub(crate) fn pointer_button_held(connection: &impl Connection, root: Window) -> Result<bool> {
+    let pointer = connection.query_pointer(root)?.reply()?;
+    let buttons = KeyButMask::BUTTON1 | KeyButMask::BUTTON2 | KeyButMask::BUTTON3;
+    Ok(pointer.mask.intersects(buttons))
+}
+
+/// What to serve once the CLIPBOARD owner exits, given what was captured from it.
+///
+/// Secrets and copies that couldn't be read leave nothing, so an older
+/// owner's copy is never served in their place.
+fn content_to_save(sensitive: bool, content: Option<&ClipboardContent>) -> Option<ClipboardContent> {
+    content.filter(|_| !sensitive).cloned()
+}
+
+/// Event-driven X11 clipboard listener.
+///
+/// Uses XFixes selection notifications, so the listener sleeps until the
+/// owner of CLIPBOARD (and optionally PRIMARY) changes instead of polling.
 pub struct X11ClipboardListener<F>
 where
-    F: Fn(String) + Send + 'static,
+    F: Fn(ClipboardEvent) + Send + 'static,
 {
-    clipboard: Clipboard,
+    context: Context,
+    /// Display name events are tagged with
+    display: Option<String>,
     on_change: F,
-    last_value: Option<String>,
+    capture_primary: bool,
+    primary_settle: Duration,
+    privacy: PrivacyConfig,
+    max_entry_size: usize,
+    on_oversized: OversizedAction,
+    /// Targets kept verbatim with each entry, and their total size limit
+    raw_targets: Vec<String>,
+    raw_targets_budget: usize,
+    last_values: HashMap<Selection, ClipboardContent>,
+    /// Serves the last copy once its owner exits, when acting as clipboard manager
+    writer: Option<Arc<dyn ClipboardWriter>>,
+    /// Last CLIPBOARD content we may serve after its owner exits; never a secret
+    saved_clipboard: Option<ClipboardContent>,
+    /// Interned atoms by name, and names of atoms seen in TARGETS replies
+    atoms: HashMap<String, Atom>,
+    atom_names: HashMap<Atom, String>,
+    /// PRIMARY change waiting for the selection to settle: (capture at, event time)
+    pending_primary: Option<(Instant, Timestamp)>,
+    /// Events read while waiting for a conversion, replayed by the main loop
+    pending: VecDeque<Event>,
 }
 
 impl<F> X11ClipboardListener<F>
 where
-    F: Fn(String) + Send + 'static,
+    F: Fn(ClipboardEvent) + Send + 'static,
 {
-    pub fn new(on_change: F) -> Result<Self> {
+    /// Create a listener on `display`, or on `$DISPLAY` if `None`.
+    ///
+    /// With a `writer` it also acts as that display's clipboard manager.
+    pub fn new(
+        config: &Config,
+        display: Option<&str>,
+        writer: Option<Arc<dyn ClipboardWriter>>,
+        on_change: F,
+    ) -> Result<Self> {
         Ok(Self {
-            clipboard: Clipboard::new()?,
+            context: Context::new(display)?,
+            display: display.map(String::from).or_else(|| std::env::var("DISPLAY").ok()),
             on_change,
-            last_value: None,
+            capture_primary: config.capture.watches_primary(),
+            primary_settle: Duration::from_millis(config.capture.primary_settle_ms),
+            privacy: config.privacy.clone(),
+            max_entry_size: config.max_entry_size,
+            on_oversized: config.on_oversized,
+            raw_targets: config.capture.raw_targets.clone(),
+            raw_targets_budget: config.capture.raw_targets_budget,
+            last_values: HashMap::new(),
+            writer: writer.filter(|_| config.capture.clipboard_manager),
+            saved_clipboard: None,
+            atoms: HashMap::new(),
+            atom_names: HashMap::new(),
+            pending_primary: None,
+            pending: VecDeque::new(),
         })
     }
+
+    fn root(&self) -> Window {
+        self.context.connection.setup().roots[self.context.screen].root
+    }
+
+    fn atom(&self, selection: Selection) -> Atom {
+        match selection {
+            Selection::Clipboard => self.context.atoms.clipboard,
+            Selection::Primary => self.context.atoms.primary,
+        }
+    }
+
+    /// Ask the X server to notify us whenever a watched selection changes owner
+    fn select_selection_input(&self) -> Result<()> {
+        let conn = &self.context.connection;
+        conn.xfixes_query_version(5, 0)?.reply()?;
+
+        let mut selections = vec![Selection::Clipboard];
+        if self.capture_primary {
+            selections.push(Selection::Primary);
+        }
+
+        for selection in selections {
+            conn.xfixes_select_selection_input(
+                self.root(),
+                self.atom(selection),
+                SelectionEventMask::SET_SELECTION_OWNER
+                    | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                    | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+            )?
+            .check()?;
+        }
+
+        Ok(())
+    }
+
+    /// Next event to process, or `None` once `deadline` has passed or `stop` is signalled
+    fn next_event(&mut self, deadline: Option<Instant>, stop: &StopSignal) -> Result<Option<Event>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+
+        wait_for_event_until(&self.context.connection, deadline, Some(stop))
+    }
+
+    /// Capture PRIMARY once it has settled, or postpone while the user is still selecting
+    fn capture_settled_primary(&mut self, timestamp: Timestamp) -> Result<()> {
+        if pointer_button_held(&self.context.connection, self.root())? {
+            self.pending_primary = Some((Instant::now() + self.primary_settle, timestamp));
+            return Ok(());
+        }
+
+        self.capture(Selection::Primary, timestamp)
+    }
+
+    fn intern(&mut self, name: &str) -> Result<Atom> {
+        if let Some(&atom) = self.atoms.get(name) {
+            return Ok(atom);
+        }
+
+        let atom = self.context.connection.intern_atom(false, name.as_bytes())?.reply()?.atom;
+        self.atoms.insert(name.to_string(), atom);
+        self.atom_names.insert(atom, name.to_string());
+        Ok(atom)
+    }
+
+    fn atom_name(&mut self, atom: Atom) -> Result<Option<String>> {
+        if let Some(name) = self.atom_names.get(&atom) {
+            return Ok(Some(name.clone()));
+        }
+
+        let reply = match self.context.connection.get_atom_name(atom)?.reply() {
+            Ok(reply) => reply,
+            // Owners can list atoms that don't exist
+            Err(ReplyError::X11Error(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let name = String::from_utf8_lossy(&reply.name).into_owned();
+        self.atom_names.insert(atom, name.clone());
+        Ok(Some(name))
+    }
+
+    /// Names of the targets the owner of `selection` offers, if it answers TARGETS
+    fn targets(&mut self, selection: Atom, timestamp: Timestamp) -> Result<Option<Vec<String>>> {
+        let Some(bytes) = self.convert(selection, self.context.atoms.targets, timestamp, usize::MAX)? else {
+            return Ok(None);
+        };
+
+        let atoms: Vec<Atom> = bytes
+            .chunks_exact(4)
+            .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+
+        let mut names = Vec::with_capacity(atoms.len());
+        let mut invalid = 0;
+        for atom in atoms {
+            match self.atom_name(atom)? {
+                Some(name) => names.push(name),
+                None => invalid += 1,
+            }
+        }
+        if invalid > 0 {
+            eprintln!("Skipped {} invalid atoms in the selection owner's TARGETS", invalid);
+        }
+        Ok(Some(names))
+    }
+
+    /// Read the current content of `selection` and report it if it changed
+    fn capture(&mut self, selection: Selection, timestamp: Timestamp) -> Result<()> {
+        let atom = self.atom(selection);
+
+        // Owners that don't answer TARGETS are asked for text directly
+        let targets = self.targets(atom, timestamp)?;
+
+        let hints = self.privacy.concealment_hints.clone();
+        let sensitive = match &targets {
+            Some(targets) => formats::is_concealed(targets, &hints, |target| {
+                self.convert_named(atom, target, timestamp)
+            })?,
+            None => false,
+        };
+        if selection == Selection::Clipboard && sensitive {
+            self.saved_clipboard = None;
+        }
+        if sensitive && self.privacy.on_concealed == ConcealedAction::Skip {
+            // Forget the previous value so copying it again is recorded
+            self.last_values.remove(&selection);
+            return Ok(());
+        }
+
+        let source = self.source_app(atom)?;
+        let owner = match source.as_ref().and_then(SourceApp::name) {
+            Some(name) => name.to_string(),
+            None => "the selection owner".to_string(),
+        };
+
+        let (max_size, on_oversized) = (self.max_entry_size, self.on_oversized);
+        let content = formats::read_content(&owner, targets.as_deref(), max_size, on_oversized, |target| {
+            self.convert_named(atom, target, timestamp)
+        })?;
+        if selection == Selection::Clipboard {
+            self.saved_clipboard = content_to_save(sensitive, content.as_ref());
+        }
+        let Some(content) = content else {
+            return Ok(());
+        };
+
+        if self.last_values.get(&selection) != Some(&content) {
+            self.last_values.insert(selection, content.clone());
+
+            let targets = targets.unwrap_or_default();
+            let (wanted, budget) = (self.raw_targets.clone(), self.raw_targets_budget);
+            // Raw data could carry the secret itself
+            let raw_targets = if sensitive {
+                Vec::new()
+            } else {
+                formats::read_raw_targets(&targets, &wanted, budget, |target| {
+                    self.convert_named(atom, target, timestamp)
+                })?
+            };
+
+            (self.on_change)(ClipboardEvent {
+                content,
+                mime_types: targets,
+                raw_targets,
+                selection,
+                source,
+                display: self.display.clone(),
+                sensitive,
+                timestamp: SystemTime::now(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Own the CLIPBOARD_MANAGER selection so applications hand us their clipboard on exit.
+    ///
+    /// Returns `false` if another clipboard manager is already running.
+    fn claim_clipboard_manager(&mut self) -> Result<bool> {
+        let manager = self.intern("CLIPBOARD_MANAGER")?;
+        let conn = &self.context.connection;
+        let window = self.context.window;
+
+        if conn.get_selection_owner(manager)?.reply()?.owner != x11rb::NONE {
+            return Ok(false);
+        }
+        conn.set_selection_owner(window, manager, CURRENT_TIME)?;
+        if conn.get_selection_owner(manager)?.reply()?.owner != window {
+            return Ok(false);
+        }
+
+        // Announce the new manager as ICCCM section 2.8 asks
+        let announce = self.intern("MANAGER")?;
+        let conn = &self.context.connection;
+        let message = ClientMessageEvent::new(32, self.root(), announce, [CURRENT_TIME, manager, window, 0, 0]);
+        conn.send_event(false, self.root(), EventMask::STRUCTURE_NOTIFY, message)?;
+        conn.flush()?;
+        Ok(true)
+    }
+
+    /// Answer a request to our CLIPBOARD_MANAGER selection.
+    ///
+    /// An exiting application converts it to SAVE_TARGETS; by then we have
+    /// usually captured its content, but make sure before confirming.
+    fn answer_manager_request(&mut self, request: SelectionRequestEvent) -> Result<()> {
+        let save_targets = self.intern("SAVE_TARGETS")?;
+        let property = if request.property == x11rb::NONE {
+            request.target
+        } else {
+            request.property
+        };
+
+        let served = if request.target == self.context.atoms.targets {
+            let targets = [self.context.atoms.targets, save_targets];
+            self.context.connection.change_property32(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                AtomEnum::ATOM,
+                &targets,
+            )?;
+            true
+        } else if request.target == save_targets {
+            self.capture(Selection::Clipboard, request.time)?;
+            let null = self.intern("NULL")?;
+            self.context.connection.change_property8(PropMode::REPLACE, request.requestor, property, null, &[])?;
+            true
+        } else {
+            false
+        };
+
+        let notify = SelectionNotifyEvent {
+            response_type: SELECTION_NOTIFY_EVENT,
+            sequence: 0,
+            time: request.time,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if served { property } else { x11rb::NONE },
+        };
+        let conn = &self.context.connection;
+        conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+        conn.flush()?;
+        Ok(())
+    }
+
+    /// Serve the last copy ourselves now that the application holding CLIPBOARD is gone
+    fn take_over_clipboard(&self) {
+        let (Some(writer), Some(content)) = (&self.writer, &self.saved_clipboard) else {
+            return;
+        };
+
+        match writer.set(Selection::Clipboard, content) {
+            Ok(()) => println!("Clipboard owner exited; serving its last copy"),
+            Err(e) => eprintln!("Failed to take over the clipboard: {:#}", e),
+        }
+    }
+
+    /// Identify the application owning `selection` from its window properties
+    fn source_app(&mut self, selection: Atom) -> Result<Option<SourceApp>> {
+        let owner = self.context.connection.get_selection_owner(selection)?.reply()?.owner;
+        if owner == x11rb::NONE {
+            return Ok(None);
+        }
+
+        // Toolkits often own selections through a hidden helper window, so fall
+        // back to the client leader and then to the owner's ancestors
+        let mut windows = vec![owner];
+        let leader = self.intern("WM_CLIENT_LEADER")?;
+        if let Some(reply) = self.window_property(owner, leader, AtomEnum::WINDOW)?
+            && let Some(window) = reply.value32().and_then(|mut v| v.next())
+        {
+            windows.push(window);
+        }
+        windows.extend(self.ancestors(owner)?);
+
+        let net_wm_pid = self.intern("_NET_WM_PID")?;
+        let mut source = SourceApp::default();
+        for window in windows {
+            if source.wm_class.is_none()
+                && let Some(reply) = self.window_property(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING)?
+            {
+                source.wm_class = wm_class(&reply.value);
+            }
+            if source.pid.is_none()
+                && let Some(reply) = self.window_property(window, net_wm_pid, AtomEnum::CARDINAL)?
+            {
+                source.pid = reply.value32().and_then(|mut v| v.next());
+            }
+            if source.wm_class.is_some() && source.pid.is_some() {
+                break;
+            }
+        }
+
+        source.executable = source.pid.and_then(executable_name);
+        Ok((source != SourceApp::default()).then_some(source))
+    }
+
+    /// Parents of `window` up to, but excluding, the root window
+    fn ancestors(&self, mut window: Window) -> Result<Vec<Window>> {
+        let root = self.root();
+        let mut ancestors = Vec::new();
+        loop {
+            let parent = match self.context.connection.query_tree(window)?.reply() {
+                Ok(reply) => reply.parent,
+                // The owner may destroy its window at any time
+                Err(ReplyError::X11Error(_)) => return Ok(ancestors),
+                Err(e) => return Err(e.into()),
+            };
+            if parent == root || parent == x11rb::NONE {
+                return Ok(ancestors);
+            }
+            ancestors.push(parent);
+            window = parent;
+        }
+    }
+
+    /// Read a property of `window`, or `None` if it is unset or the window is gone
+    fn window_property(
+        &self,
+        window: Window,
+        property: Atom,
+        type_: impl Into<Atom>,
+    ) -> Result<Option<GetPropertyReply>> {
+        let cookie = self.context.connection.get_property(false, window, property, type_, 0, 1024)?;
+        match cookie.reply() {
+            Ok(reply) if reply.type_ != x11rb::NONE => Ok(Some(reply)),
+            Ok(_) | Err(ReplyError::X11Error(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Convert `selection` to the target called `name`, reading at most one byte past `max_entry_size`
+    fn convert_named(&mut self, selection: Atom, name: &str, timestamp: Timestamp) -> Result<Option<Vec<u8>>> {
+        let target = self.intern(name)?;
+        self.convert(selection, target, timestamp, self.max_entry_size.saturating_add(1))
+    }
+
+    /// Convert `selection` to `target` and return up to `limit` transferred bytes.
+    ///
+    /// Returns `Ok(None)` when the owner refuses the conversion or does not
+    /// answer in time. Errors are reserved for a broken X connection.
+    fn convert(&mut self, selection: Atom, target: Atom, timestamp: Timestamp, limit: usize) -> Result<Option<Vec<u8>>> {
+        let conn = &self.context.connection;
+        let window = self.context.window;
+        let property = self.context.atoms.property;
+
+        conn.convert_selection(window, selection, target, property, timestamp)?;
+        conn.flush()?;
+
+        let deadline = Instant::now() + CONVERT_TIMEOUT;
+        loop {
+            let Some(event) = wait_for_event_until(conn, Some(deadline), None)? else {
+                return Ok(None);
+            };
+
+            match event {
+                Event::SelectionNotify(event)
+                    if event.requestor == window && event.selection == selection && event.target == target =>
+                {
+                    // A property of None means the owner could not convert the selection
+                    if event.property == Atom::from(AtomEnum::NONE) {
+                        return Ok(None);
+                    }
+
+                    // Property lengths are counted in 32-bit units
+                    let length = limit.div_ceil(4).min(u32::MAX as usize) as u32;
+                    let reply = conn
+                        .get_property(true, window, event.property, AtomEnum::ANY, 0, length)?
+                        .reply()?;
+
+                    if reply.type_ == self.context.atoms.incr {
+                        return self.read_incr(event.property, limit);
+                    }
+
+                    // The server only deletes the property once it has been read in full
+                    if reply.bytes_after > 0 {
+                        conn.delete_property(window, event.property)?;
+                    }
+
+                    let mut value = reply.value;
+                    value.truncate(limit);
+                    return Ok(Some(value));
+                }
+                // A late answer to an earlier conversion that timed out
+                Event::SelectionNotify(event) if event.requestor == window => {}
+                other => self.pending.push_back(other),
+            }
+        }
+    }
+
+    /// Read an incremental (INCR) transfer of up to `limit` bytes.
+    ///
+    /// The owner writes a chunk each time we delete the property and ends with
+    /// an empty one. Each chunk gets its own timeout, so slow but steady
+    /// transfers of any size complete.
+    fn read_incr(&mut self, property: Atom, limit: usize) -> Result<Option<Vec<u8>>> {
+        let conn = &self.context.connection;
+        let window = self.context.window;
+
+        // Reading the INCR property deleted it, which asks for the first chunk
+        let mut data = Vec::new();
+        loop {
+            let deadline = Instant::now() + INCR_CHUNK_TIMEOUT;
+            loop {
+                let Some(event) = wait_for_event_until(conn, Some(deadline), None)? else {
+                    return Ok(None);
+                };
+
+                match event {
+                    Event::PropertyNotify(event)
+                        if event.window == window
+                            && event.atom == property
+                            && event.state == Property::NEW_VALUE =>
+                    {
+                        break;
+                    }
+                    other => self.pending.push_back(other),
+                }
+            }
+
+            let reply = conn
+                .get_property(true, window, property, AtomEnum::ANY, 0, u32::MAX)?
+                .reply()?;
+            if reply.value.is_empty() {
+                return Ok(Some(data));
+            }
+
+            data.extend_from_slice(&reply.value);
+            if data.len() >= limit {
+                // Stop asking for chunks; the owner gives up on its own
+                data.truncate(limit);
+                return Ok(Some(data));
+            }
+        }
+    }
 }
 
 impl<F> ClipboardListener for X11ClipboardListener<F>
 where
-    F: Fn(String) + Send + 'static,
+    F: Fn(ClipboardEvent) + Send + 'static,
 {
-    fn start(&mut self) -> Result<()> {
+    fn start(&mut self, stop: &StopSignal) -> Result<()> {
+        self.select_selection_input()?;
+
+        if self.writer.is_some() && !self.claim_clipboard_manager()? {
+            eprintln!("Another clipboard manager is running; copies will not outlive their application");
+            self.writer = None;
+        }
+        let manager = self.intern("CLIPBOARD_MANAGER")?;
+
+        // Pick up whatever is already on the clipboard
+        self.capture(Selection::Clipboard, CURRENT_TIME)?;
+
         loop {
-            if let Ok(text) = self.clipboard.load(
-                self.clipboard.getter.atoms.clipboard,
-                self.clipboard.getter.atoms.utf8_string,
-                self.clipboard.getter.atoms.property,
-                Duration::from_millis(100),
-            ) {
-                if let Ok(text_sting) = String::from_utf8(text) {
-                    if self.last_value.as_ref() != Some(&text_sting) {
-                        self.last_value = Some(text_sting.clone());
-                        (self.on_change)(text_sting);
+            let deadline = self.pending_primary.map(|(at, _)| at);
+
+            let event = self.next_event(deadline, stop)?;
+            if stop.is_stopped() {
+                return Ok(());
+            }
+
+            match event {
+                Some(Event::XfixesSelectionNotify(event))
+                    if event.subtype == xfixes::SelectionEvent::SET_SELECTION_OWNER
+                        && event.owner != x11rb::NONE =>
+                {
+                    if event.selection == self.context.atoms.clipboard {
+                        self.capture(Selection::Clipboard, event.timestamp)?;
+                    } else if event.selection == self.context.atoms.primary {
+                        // Wait for the selection to settle before reading it
+                        let at = Instant::now() + self.primary_settle;
+                        self.pending_primary = Some((at, event.timestamp));
+                    }
+                }
+                Some(Event::XfixesSelectionNotify(event))
+                    if event.selection == self.context.atoms.clipboard
+                        && matches!(
+                            event.subtype,
+                            xfixes::SelectionEvent::SELECTION_WINDOW_DESTROY
+                                | xfixes::SelectionEvent::SELECTION_CLIENT_CLOSE
+                        ) =>
+                {
+                    self.take_over_clipboard();
+                }
+                Some(Event::SelectionRequest(request)) if request.selection == manager => {
+                    self.answer_manager_request(request)?;
+                }
+                Some(_) => {}
+                None => {
+                    if let Some((_, timestamp)) = self.pending_primary.take() {
+                        self.capture_settled_primary(timestamp)?;
                     }
                 }
             }
+        }
+    }
+}
+
+/// Targets we offer for each selection we own, with their data
+type Offers = Arc<Mutex<HashMap<Atom, Vec<(Atom, Vec<u8>)>>>>;
+
+/// Serves clipboard content to other X11 clients.
+///
+/// A background thread answers conversion requests until another client
+/// takes the selection over.
+pub struct X11ClipboardWriter {
+    context: Arc<Context>,
+    offers: Offers,
+}
+
+impl X11ClipboardWriter {
+    /// Create a writer on `display`, or on `$DISPLAY` if `None`
+    pub fn new(display: Option<&str>) -> Result<Self> {
+        let context = Arc::new(Context::new(display)?);
+        let offers = Offers::default();
+
+        let server = SelectionServer {
+            context: context.clone(),
+            offers: offers.clone(),
+            transfers: HashMap::new(),
+        };
+        thread::Builder::new()
+            .name("x11-clipboard-writer".to_string())
+            .spawn(move || {
+                if let Err(e) = server.run() {
+                    eprintln!("X11 clipboard writer stopped: {:#}", e);
+                }
+            })?;
+
+        Ok(Self { context, offers })
+    }
+}
+
+impl ClipboardWriter for X11ClipboardWriter {
+    fn set(&self, selection: Selection, content: &ClipboardContent) -> Result<()> {
+        let conn = &self.context.connection;
+        let selection = match selection {
+            Selection::Clipboard => self.context.atoms.clipboard,
+            Selection::Primary => self.context.atoms.primary,
+        };
+
+        let offered = formats::offered_targets(content);
+        let cookies = offered
+            .iter()
+            .map(|(name, _)| conn.intern_atom(false, name.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut targets = Vec::with_capacity(offered.len());
+        for (cookie, (_, data)) in cookies.into_iter().zip(offered) {
+            targets.push((cookie.reply()?.atom, data));
+        }
+        self.offers.lock().unwrap().insert(selection, targets);
 
-            thread::sleep(Duration::from_millis(300));
+        conn.set_selection_owner(self.context.window, selection, CURRENT_TIME)?;
+        if conn.get_selection_owner(selection)?.reply()?.owner != self.context.window {
+            self.offers.lock().unwrap().remove(&selection);
+            bail!("Could not take ownership of the selection");
         }
+
+        Ok(())
+    }
+}
+
+/// Data too large for one property, sent in chunks as the requestor reads them
+struct IncrTransfer {
+    target: Atom,
+    data: Vec<u8>,
+    offset: usize,
+}
+
+/// Answers selection requests for an `X11ClipboardWriter`
+struct SelectionServer {
+    context: Arc<Context>,
+    offers: Offers,
+    /// Incremental transfers in progress by requestor window and property
+    transfers: HashMap<(Window, Atom), IncrTransfer>,
+}
+
+impl SelectionServer {
+    fn run(mut self) -> Result<()> {
+        loop {
+            match self.context.connection.wait_for_event()? {
+                Event::SelectionRequest(request) => self.answer(request)?,
+                Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                    self.continue_transfer(event.window, event.atom)?;
+                }
+                Event::SelectionClear(event) => {
+                    self.offers.lock().unwrap().remove(&event.selection);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn chunk_size(&self) -> usize {
+        INCR_CHUNK_SIZE.min(self.context.connection.maximum_request_bytes() - 64)
+    }
+
+    fn answer(&mut self, request: SelectionRequestEvent) -> Result<()> {
+        // Obsolete clients leave the property unset and expect the target name
+        let property = if request.property == x11rb::NONE {
+            request.target
+        } else {
+            request.property
+        };
+        let served = self.serve(&request, property)?;
+
+        let notify = SelectionNotifyEvent {
+            response_type: SELECTION_NOTIFY_EVENT,
+            sequence: 0,
+            time: request.time,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if served { property } else { x11rb::NONE },
+        };
+        let conn = &self.context.connection;
+        conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+        conn.flush()?;
+        Ok(())
+    }
+
+    /// Write the requested target to `property`, returning whether we had it
+    fn serve(&mut self, request: &SelectionRequestEvent, property: Atom) -> Result<bool> {
+        let chunk_size = self.chunk_size();
+        let conn = &self.context.connection;
+        let atoms = &self.context.atoms;
+        let offers = self.offers.lock().unwrap();
+        let Some(targets) = offers.get(&request.selection) else {
+            return Ok(false);
+        };
+
+        if request.target == atoms.targets {
+            let mut names = vec![atoms.targets];
+            names.extend(targets.iter().map(|&(target, _)| target));
+            conn.change_property32(PropMode::REPLACE, request.requestor, property, AtomEnum::ATOM, &names)?;
+            return Ok(true);
+        }
+
+        let Some((target, data)) = targets.iter().find(|(target, _)| *target == request.target) else {
+            return Ok(false);
+        };
+
+        if data.len() > chunk_size {
+            // Announce the size, then send chunks each time the requestor deletes the property
+            let attributes = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
+            conn.change_window_attributes(request.requestor, &attributes)?;
+            conn.change_property32(PropMode::REPLACE, request.requestor, property, atoms.incr, &[data.len() as u32])?;
+            self.transfers.insert(
+                (request.requestor, property),
+                IncrTransfer {
+                    target: *target,
+                    data: data.clone(),
+                    offset: 0,
+                },
+            );
+        } else {
+            conn.change_property8(PropMode::REPLACE, request.requestor, property, *target, data)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Send the next chunk of an incremental transfer; an empty chunk ends it
+    fn continue_transfer(&mut self, window: Window, property: Atom) -> Result<()> {
+        let chunk_size = self.chunk_size();
+        let Some(transfer) = self.transfers.get_mut(&(window, property)) else {
+            return Ok(());
+        };
+
+        let end = (transfer.offset + chunk_size).min(transfer.data.len());
+        let chunk = &transfer.data[transfer.offset..end];
+        let conn = &self.context.connection;
+        conn.change_property8(PropMode::REPLACE, window, property, transfer.target, chunk)?;
+        conn.flush()?;
+
+        if chunk.is_empty() {
+            self.transfers.remove(&(window, property));
+        } else {
+            transfer.offset = end;
+        }
+
+        Ok(())
+    }
+}
+
+/// Class name from a WM_CLASS value ("instance\0class\0"), falling back to the instance name
+fn wm_class(value: &[u8]) -> Option<String> {
+    let mut parts = value
+        .split(|&b| b == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).into_owned());
+    let instance = parts.next();
+    parts.next().or(instance)
+}
+
+/// File name of the executable running as `pid`
+fn executable_name(pid: u32) -> Option<String> {
+    if let Ok(path) = std::fs::read_link(format!("/proc/{}/exe", pid)) {
+        return path.file_name().map(|name| name.to_string_lossy().into_owned());
+    }
+
+    // exe is unreadable for other users' processes, but comm is not
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(comm.trim_end().to_string()).filter(|name| !name.is_empty())
+}
+
+/// Wait for the next event, giving up once `deadline` has passed or `stop` is signalled
+fn wait_for_event_until(conn: &RustConnection, deadline: Option<Instant>, stop: Option<&StopSignal>) -> Result<Option<Event>> {
+    loop {
+        if let Some(event) = conn.poll_for_event()? {
+            return Ok(Some(event));
+        }
+
+        if !poll::wait_readable_or_stopped(conn.stream().as_fd(), deadline, stop)? {
+            return Ok(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uncaptured_copy_clears_saved_clipboard() {
+        let copy = ClipboardContent::from("hello".to_string());
+        assert_eq!(content_to_save(false, Some(&copy)), Some(copy.clone()));
+        assert_eq!(content_to_save(true, Some(&copy)), None);
+        // An unsupported, refused or skipped copy replaces the previous one with nothing
+        assert_eq!(content_to_save(false, None), None);
     }
 }
@@ -0,0 +1,317 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{FileList, FileOperation};
+
+/// Content of a clipboard entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClipboardContent {
+    /// Plain text, with the rich text formats the owner offered alongside it
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        html: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rtf: Option<String>,
+    },
+    Image(ImageContent),
+    /// Files copied or cut in a file manager
+    Files(FileList),
+}
+
+/// Encoded image as offered by the selection owner
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageContent {
+    /// MIME type of `data`, e.g. "image/png"
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    /// Size of the encoded image in bytes
+    pub size: usize,
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub data: Vec<u8>,
+}
+
+/// Data of a target kept verbatim, for inspecting what an application offered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawTarget {
+    pub target: String,
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub data: Vec<u8>,
+}
+
+/// Image MIME types we know how to capture, in order of preference
+pub const IMAGE_MIME_TYPES: &[&str] = &["image/png", "image/jpeg", "image/bmp"];
+
+impl ClipboardContent {
+    /// Text of the entry, if it is a text entry
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Self::Text { text, .. } => Some(text),
+            Self::Image(_) | Self::Files(_) => None,
+        }
+    }
+
+    /// Size of the content in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Text { text, html, rtf } => {
+                text.len()
+                    + html.as_ref().map_or(0, String::len)
+                    + rtf.as_ref().map_or(0, String::len)
+            }
+            Self::Image(image) => image.size,
+            Self::Files(files) => files.paths.iter().map(|p| p.as_os_str().len()).sum(),
+        }
+    }
+
+    /// Whether the entry carries formatting next to its plain text
+    pub fn is_rich_text(&self) -> bool {
+        matches!(self, Self::Text { html, rtf, .. } if html.is_some() || rtf.is_some())
+    }
+
+    /// Short human-readable description, used where the content itself can't be shown
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Text { text, .. } => text.clone(),
+            Self::Image(image) => format!(
+                "Image ({}, {}×{}, {})",
+                image.format_name(),
+                image.width,
+                image.height,
+                format_size(image.size)
+            ),
+            Self::Files(files) => {
+                let verb = match files.operation {
+                    FileOperation::Copy => "Copied",
+                    FileOperation::Cut => "Cut",
+                };
+                let names: Vec<_> = files
+                    .paths
+                    .iter()
+                    .map(|path| path.file_name().unwrap_or(path.as_os_str()).to_string_lossy())
+                    .collect();
+                match names.len() {
+                    1 => format!("{} file: {}", verb, names[0]),
+                    n => format!("{} {} files: {}", verb, n, names.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+impl From<String> for ClipboardContent {
+    fn from(text: String) -> Self {
+        Self::Text {
+            text,
+            html: None,
+            rtf: None,
+        }
+    }
+}
+
+impl From<ImageContent> for ClipboardContent {
+    fn from(image: ImageContent) -> Self {
+        Self::Image(image)
+    }
+}
+
+impl From<FileList> for ClipboardContent {
+    fn from(files: FileList) -> Self {
+        Self::Files(files)
+    }
+}
+
+impl ImageContent {
+    /// Build an image from encoded bytes, reading the dimensions from its header.
+    ///
+    /// Returns `None` if the data is not a valid image of the given type.
+    pub fn from_bytes(mime_type: &str, data: Vec<u8>) -> Option<Self> {
+        let (width, height) = match mime_type {
+            "image/png" => png_dimensions(&data)?,
+            "image/jpeg" => jpeg_dimensions(&data)?,
+            "image/bmp" => bmp_dimensions(&data)?,
+            _ => return None,
+        };
+
+        Some(Self {
+            mime_type: mime_type.to_string(),
+            width,
+            height,
+            size: data.len(),
+            data,
+        })
+    }
+
+    /// Upper-case format name derived from the MIME type, e.g. "PNG"
+    pub fn format_name(&self) -> String {
+        self.mime_type
+            .strip_prefix("image/")
+            .unwrap_or(&self.mime_type)
+            .to_uppercase()
+    }
+}
+
+/// Format a byte count for display, e.g. "45 KB"
+pub fn format_size(bytes: usize) -> String {
+    const KB: usize = 1024;
+    const MB: usize = 1024 * KB;
+
+    if bytes >= MB {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{} KB", bytes / KB)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    // The IHDR chunk always comes first and starts with width and height
+    if !data.starts_with(SIGNATURE) || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+
+    let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    // Walk the marker segments until we reach a start-of-frame
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+
+        // SOF0..SOF15, excluding DHT (C4), JPG (C8) and DAC (CC)
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let height = u16::from_be_bytes([*data.get(pos + 5)?, *data.get(pos + 6)?]);
+            let width = u16::from_be_bytes([*data.get(pos + 7)?, *data.get(pos + 8)?]);
+            return Some((width as u32, height as u32));
+        }
+
+        pos += 2 + length;
+    }
+}
+
+fn bmp_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(b"BM") {
+        return None;
+    }
+
+    let header_size = u32::from_le_bytes(data.get(14..18)?.try_into().ok()?);
+    if header_size == 12 {
+        // BITMAPCOREHEADER stores 16-bit dimensions
+        let width = u16::from_le_bytes(data.get(18..20)?.try_into().ok()?);
+        let height = u16::from_le_bytes(data.get(20..22)?.try_into().ok()?);
+        return Some((width as u32, height as u32));
+    }
+
+    // Height is negative for top-down bitmaps
+    let width = i32::from_le_bytes(data.get(18..22)?.try_into().ok()?);
+    let height = i32::from_le_bytes(data.get(22..26)?.try_into().ok()?);
+    Some((width.unsigned_abs(), height.unsigned_abs()))
+}
+
+fn serialize_base64<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&STANDARD.encode(data))
+}
+
+fn deserialize_base64<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let encoded = String::deserialize(deserializer)?;
+    STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_image_dimensions() {
+        let png = ImageContent::from_bytes("image/png", png_header(800, 600)).unwrap();
+        assert_eq!((png.width, png.height), (800, 600));
+        assert_eq!(png.size, 24);
+
+        // APP0 segment followed by a baseline SOF0 frame
+        let jpeg = vec![
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01,
+            0xE0, 0x02, 0x80,
+        ];
+        let jpeg = ImageContent::from_bytes("image/jpeg", jpeg).unwrap();
+        assert_eq!((jpeg.width, jpeg.height), (640, 480));
+
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&[0; 12]);
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&32i32.to_le_bytes());
+        bmp.extend_from_slice(&(-16i32).to_le_bytes());
+        let bmp = ImageContent::from_bytes("image/bmp", bmp).unwrap();
+        assert_eq!((bmp.width, bmp.height), (32, 16));
+
+        assert!(ImageContent::from_bytes("image/png", b"not an image".to_vec()).is_none());
+    }
+
+    #[test]
+    fn test_image_roundtrip() {
+        let content = ClipboardContent::from(ImageContent::from_bytes("image/png", png_header(1, 1)).unwrap());
+        let json = serde_json::to_string(&content).unwrap();
+        assert!(json.contains("\"kind\":\"image\""));
+
+        let decoded: ClipboardContent = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, content);
+        assert_eq!(decoded.summary(), "Image (PNG, 1×1, 24 B)");
+    }
+
+    #[test]
+    fn test_rich_text_roundtrip() {
+        let plain = ClipboardContent::from("Hello".to_string());
+        assert_eq!(serde_json::to_string(&plain).unwrap(), r#"{"kind":"text","text":"Hello"}"#);
+        assert!(!plain.is_rich_text());
+
+        let rich = ClipboardContent::Text {
+            text: "Hello".to_string(),
+            html: Some("<b>Hello</b>".to_string()),
+            rtf: None,
+        };
+        let decoded: ClipboardContent = serde_json::from_str(&serde_json::to_string(&rich).unwrap()).unwrap();
+        assert_eq!(decoded, rich);
+        assert!(decoded.is_rich_text());
+        assert_eq!(decoded.size(), 17);
+    }
+
+    #[test]
+    fn test_files_roundtrip() {
+        let files = ClipboardContent::from(FileList::from_uri_list("file:///tmp/a.txt\r\nfile:///tmp/b.txt").unwrap());
+        let json = serde_json::to_string(&files).unwrap();
+        assert_eq!(json, r#"{"kind":"files","operation":"copy","paths":["/tmp/a.txt","/tmp/b.txt"]}"#);
+
+        let decoded: ClipboardContent = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, files);
+        assert_eq!(decoded.summary(), "Copied 2 files: a.txt, b.txt");
+    }
+}
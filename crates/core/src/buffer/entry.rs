@@ -1,23 +1,162 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
 
+use super::{ClipboardContent, RawTarget};
+
+/// X selection an entry was captured from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    /// Explicit copies (Ctrl+C)
+    #[default]
+    Clipboard,
+    /// Highlighted text, pasted with the middle mouse button
+    Primary,
+}
+
+/// Application that owned the selection when an entry was captured
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SourceApp {
+    /// Class part of the owner window's WM_CLASS, e.g. "firefox"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wm_class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// File name of the owner's executable, e.g. "firefox-bin"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<String>,
+}
+
+impl SourceApp {
+    /// Name to show for the application, if anything identifies it
+    pub fn name(&self) -> Option<&str> {
+        self.wm_class.as_deref().or(self.executable.as_deref())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClipboardEntry {
-    pub text: String,
+    /// Stable identifier assigned by `ClipboardBuffer`; 0 until the entry is added
+    #[serde(default)]
+    pub id: u64,
+    pub content: ClipboardContent,
     #[serde(
         serialize_with = "serialize_systemtime",
         deserialize_with = "deserialize_systemtime"
     )]
     pub timestamp: SystemTime,
+    #[serde(default)]
+    pub selection: Selection,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceApp>,
+    /// X display the entry was copied on, e.g. ":1"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+    /// Copied with a password-manager hint; kept in memory only
+    #[serde(default)]
+    pub sensitive: bool,
+    /// Kept forever: never evicted and not counted toward `max_entries`
+    #[serde(default)]
+    pub pinned: bool,
+    /// Content hidden in the popup, as set by a privacy rule
+    #[serde(default)]
+    pub masked: bool,
+    /// Kept in memory only, as set by a privacy rule
+    #[serde(default)]
+    pub transient: bool,
+    /// When the entry is removed from the history, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Targets (X11) or MIME types (Wayland) the owner offered
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    /// Verbatim data of the targets listed in `capture.raw_targets`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub raw_targets: Vec<RawTarget>,
 }
 
 impl ClipboardEntry {
-    pub fn new(text: String) -> Self {
+    pub fn new(content: impl Into<ClipboardContent>) -> Self {
         Self {
-            text,
+            id: 0,
+            content: content.into(),
             timestamp: SystemTime::now(),
+            selection: Selection::default(),
+            source: None,
+            display: None,
+            sensitive: false,
+            pinned: false,
+            masked: false,
+            transient: false,
+            expires_at: None,
+            targets: Vec::new(),
+            raw_targets: Vec::new(),
         }
     }
+
+    /// Set when the entry was captured
+    pub fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Set the selection this entry was captured from
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Set the application the entry was copied from
+    pub fn with_source(mut self, source: Option<SourceApp>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Set the X display the entry was copied on
+    pub fn with_display(mut self, display: Option<String>) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Mark the entry as a secret that must not be written to disk
+    pub fn with_sensitive(mut self, sensitive: bool) -> Self {
+        self.sensitive = sensitive;
+        self
+    }
+
+    /// Set the targets the owner offered
+    pub fn with_targets(mut self, targets: Vec<String>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// Set the verbatim data kept for some of the targets
+    pub fn with_raw_targets(mut self, raw_targets: Vec<RawTarget>) -> Self {
+        self.raw_targets = raw_targets;
+        self
+    }
+
+    /// Whether the entry must not be written to disk
+    pub fn is_transient(&self) -> bool {
+        self.sensitive || self.transient
+    }
+
+    /// Whether the entry's content is hidden in the popup
+    pub fn is_masked(&self) -> bool {
+        self.sensitive || self.masked
+    }
+
+    /// Whether the entry has expired at `now`
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| {
+            now.duration_since(UNIX_EPOCH).is_ok_and(|now| now.as_secs() >= expires_at)
+        })
+    }
+
+    /// Text of the entry, if it is a text entry
+    pub fn text(&self) -> Option<&str> {
+        self.content.text()
+    }
 }
 
 // Helper functions for SystemTime serialization
@@ -0,0 +1,137 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+/// Characters left unescaped in file URIs
+const URI_PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Whether files were copied or cut in the file manager
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOperation {
+    #[default]
+    Copy,
+    Cut,
+}
+
+/// Files copied in a file manager
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileList {
+    pub operation: FileOperation,
+    pub paths: Vec<PathBuf>,
+}
+
+impl FileList {
+    /// Parse an `x-special/gnome-copied-files` transfer: the operation, then one URI per line
+    pub fn from_gnome_copied_files(data: &str) -> Option<Self> {
+        let mut lines = data.lines();
+        let operation = match lines.next()?.trim() {
+            "copy" => FileOperation::Copy,
+            "cut" => FileOperation::Cut,
+            _ => return None,
+        };
+
+        let paths: Vec<PathBuf> = lines.filter_map(uri_to_path).collect();
+        (!paths.is_empty()).then_some(Self { operation, paths })
+    }
+
+    /// Parse a `text/uri-list` transfer (RFC 2483), keeping only local files
+    pub fn from_uri_list(data: &str) -> Option<Self> {
+        let paths: Vec<PathBuf> = data
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(uri_to_path)
+            .collect();
+
+        (!paths.is_empty()).then_some(Self {
+            operation: FileOperation::Copy,
+            paths,
+        })
+    }
+
+    pub fn to_gnome_copied_files(&self) -> String {
+        let operation = match self.operation {
+            FileOperation::Copy => "copy",
+            FileOperation::Cut => "cut",
+        };
+
+        let mut lines = vec![operation.to_string()];
+        lines.extend(self.uris());
+        lines.join("\n")
+    }
+
+    pub fn to_uri_list(&self) -> String {
+        self.uris().map(|uri| uri + "\r\n").collect()
+    }
+
+    /// Paths one per line, for targets that only understand plain text
+    pub fn to_text(&self) -> String {
+        self.paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn uris(&self) -> impl Iterator<Item = String> + '_ {
+        self.paths
+            .iter()
+            .map(|path| format!("file://{}", percent_encode(path.as_os_str().as_bytes(), URI_PATH)))
+    }
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.trim().strip_prefix("file://")?;
+
+    // Skip the authority ("localhost" or empty) up to the path
+    let path = &path[path.find('/')?..];
+    let bytes: Vec<u8> = percent_decode_str(path).collect();
+    Some(PathBuf::from(OsStr::from_bytes(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gnome_copied_files() {
+        let files = FileList::from_gnome_copied_files(
+            "cut\nfile:///home/user/My%20Notes.txt\nfile:///tmp/a.png",
+        )
+        .unwrap();
+
+        assert_eq!(files.operation, FileOperation::Cut);
+        assert_eq!(
+            files.paths,
+            vec![PathBuf::from("/home/user/My Notes.txt"), PathBuf::from("/tmp/a.png")]
+        );
+        assert_eq!(
+            files.to_gnome_copied_files(),
+            "cut\nfile:///home/user/My%20Notes.txt\nfile:///tmp/a.png"
+        );
+
+        assert!(FileList::from_gnome_copied_files("move\nfile:///tmp/a").is_none());
+    }
+
+    #[test]
+    fn test_uri_list() {
+        let files = FileList::from_uri_list(
+            "# comment\r\nfile://localhost/tmp/a%23b\r\nhttps://example.com/\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(files.operation, FileOperation::Copy);
+        assert_eq!(files.paths, vec![PathBuf::from("/tmp/a#b")]);
+        assert_eq!(files.to_uri_list(), "file:///tmp/a%23b\r\n");
+
+        assert!(FileList::from_uri_list("https://example.com/").is_none());
+    }
+}
@@ -1,5 +1,9 @@
+mod content;
 mod entry;
+mod files;
 mod ring_buffer;
 
-pub use entry::ClipboardEntry;
+pub use content::{format_size, ClipboardContent, ImageContent, RawTarget, IMAGE_MIME_TYPES};
+pub use entry::{ClipboardEntry, Selection, SourceApp};
+pub use files::{FileList, FileOperation};
 pub use ring_buffer::ClipboardBuffer;
@@ -1,5 +1,6 @@
 use std::collections::VecDeque;
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use anyhow::Result;
 
 use super::ClipboardEntry;
@@ -8,6 +9,8 @@ use crate::persistence;
 pub struct ClipboardBuffer {
     max_entries: usize,
     entries: VecDeque<ClipboardEntry>,
+    /// Id given to the next new entry
+    next_id: u64,
 }
 
 impl ClipboardBuffer {
@@ -15,6 +18,7 @@ impl ClipboardBuffer {
         Self {
             max_entries,
             entries: VecDeque::with_capacity(max_entries),
+            next_id: 1,
         }
     }
 
@@ -25,19 +29,135 @@ impl ClipboardBuffer {
         Ok(buffer)
     }
 
-    pub fn push(&mut self, entry: ClipboardEntry) {
-        // Deduplicate exact text
-        if let Some(pos) = self.entries.iter().position(|e| e.text == entry.text) {
-            self.entries.remove(pos);
+    /// Add an entry as the newest and return its id.
+    ///
+    /// Copying the content of an existing entry again moves that entry to the
+    /// front, keeping its id and pin.
+    pub fn push(&mut self, mut entry: ClipboardEntry) -> u64 {
+        // Deduplicate exact content
+        match self.entries.iter().position(|e| e.content == entry.content) {
+            Some(pos) => {
+                let existing = self.entries.remove(pos).unwrap();
+                entry.id = existing.id;
+                entry.pinned = existing.pinned;
+            }
+            None => entry.id = self.allocate_id(),
         }
+        let id = entry.id;
 
         self.entries.push_front(entry);
+        self.evict();
+        id
+    }
+
+    /// Replace the entry with `id`, e.g. with a later version of the same copy; it keeps its id.
+    ///
+    /// Only the newest entry is replaced: if another entry was brought up since,
+    /// or `id` is pinned, `entry` is pushed instead.
+    pub fn replace_newest(&mut self, id: u64, mut entry: ClipboardEntry) -> u64 {
+        // Pinned entries keep their content
+        if self.entries.front().is_none_or(|e| e.id != id || e.pinned) {
+            return self.push(entry);
+        }
+        let newest = self.entries.pop_front().unwrap();
+
+        match self.entries.iter().position(|e| e.content == entry.content) {
+            // The new content is already in the history, so that entry moves up instead
+            Some(_) => self.push(entry),
+            None => {
+                entry.id = newest.id;
+                self.entries.push_front(entry);
+                newest.id
+            }
+        }
+    }
+
+    /// Entry with the given id
+    pub fn get(&self, id: u64) -> Option<&ClipboardEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    /// Remove the entry with the given id
+    pub fn remove(&mut self, id: u64) -> Option<ClipboardEntry> {
+        let pos = self.entries.iter().position(|e| e.id == id)?;
+        self.entries.remove(pos)
+    }
+
+    /// Change the entry with the given id in place; its id can't be changed.
+    ///
+    /// Returns `false` if there is no such entry.
+    pub fn update(&mut self, id: u64, f: impl FnOnce(&mut ClipboardEntry)) -> bool {
+        let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) else {
+            return false;
+        };
+        f(entry);
+        entry.id = id;
+        true
+    }
+
+    /// Make the entry with the given id the newest; returns `false` if there is no such entry
+    pub fn move_to_front(&mut self, id: u64) -> bool {
+        match self.remove(id) {
+            Some(entry) => {
+                self.entries.push_front(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pin the entry with the given id; returns `false` if there is no such entry
+    pub fn pin(&mut self, id: u64) -> bool {
+        self.update(id, |e| e.pinned = true)
+    }
+
+    /// Unpin the entry with the given id, evicting the oldest entry if that
+    /// brings the history over `max_entries`. Returns `false` if there is no such entry.
+    pub fn unpin(&mut self, id: u64) -> bool {
+        let found = self.update(id, |e| e.pinned = false);
+        self.evict();
+        found
+    }
+
+    /// Pinned entries, most recent first
+    pub fn pinned(&self) -> impl Iterator<Item = &ClipboardEntry> {
+        self.entries.iter().filter(|e| e.pinned)
+    }
 
-        if self.entries.len() > self.max_entries {
-            self.entries.pop_back();
+    /// Remove entries that have expired at `now`, pinned or not; returns how many were removed
+    pub fn remove_expired(&mut self, now: SystemTime) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|e| !e.is_expired(now));
+        len - self.entries.len()
+    }
+
+    /// When the next entry expires
+    pub fn next_expiry(&self) -> Option<SystemTime> {
+        self.entries
+            .iter()
+            .filter_map(|e| e.expires_at)
+            .min()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Drop the oldest unpinned entries beyond `max_entries`
+    fn evict(&mut self) {
+        let mut unpinned = self.entries.iter().filter(|e| !e.pinned).count();
+        while unpinned > self.max_entries {
+            let Some(pos) = self.entries.iter().rposition(|e| !e.pinned) else {
+                break;
+            };
+            self.entries.remove(pos);
+            unpinned -= 1;
         }
     }
 
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
     pub fn entries(&self) -> impl Iterator<Item = &ClipboardEntry> {
         self.entries.iter()
     }
@@ -60,19 +180,94 @@ impl ClipboardBuffer {
     /// Save entries to a file
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let entries_vec = self.entries_vec();
-        persistence::save_to_file(path, &entries_vec)
+        persistence::save_to_file(path, &entries_vec, self.next_id)
     }
 
     /// Load entries from a file
     pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let loaded_entries = persistence::load_from_file(path)?;
+        let history = persistence::load_from_file(path)?;
 
         // Clear existing and load persisted entries
         self.entries.clear();
-        for entry in loaded_entries.into_iter().take(self.max_entries) {
+        let after_loaded = history.entries.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+        self.next_id = history.next_id.unwrap_or(0).max(after_loaded);
+        for mut entry in history.entries {
+            // Written before entries had ids
+            if entry.id == 0 {
+                entry.id = self.allocate_id();
+            }
             self.entries.push_back(entry);
         }
+        self.remove_expired(SystemTime::now());
+        self.evict();
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_stable() {
+        let mut buffer = ClipboardBuffer::new(10);
+        let hello = buffer.push(ClipboardEntry::new("hello".to_string()));
+        let world = buffer.push(ClipboardEntry::new("world".to_string()));
+        assert_ne!(hello, world);
+
+        // Copying "hello" again moves it up without a new id
+        assert_eq!(buffer.push(ClipboardEntry::new("hello".to_string())), hello);
+        assert_eq!(buffer.entries().next().map(|e| e.id), Some(hello));
+
+        assert!(buffer.move_to_front(world));
+        assert!(buffer.update(world, |e| e.sensitive = true));
+        assert!(buffer.get(world).unwrap().sensitive);
+
+        assert_eq!(buffer.remove(hello).and_then(|e| e.text().map(String::from)), Some("hello".to_string()));
+        assert!(buffer.get(hello).is_none());
+        assert!(!buffer.move_to_front(hello));
+
+        // Coalescing replaces an entry only while it is still the newest
+        let one = buffer.push(ClipboardEntry::new("one".to_string()));
+        assert_eq!(buffer.replace_newest(one, ClipboardEntry::new("one two".to_string())), one);
+        assert!(buffer.move_to_front(world));
+        assert_ne!(buffer.replace_newest(one, ClipboardEntry::new("one two three".to_string())), one);
+        assert_eq!(buffer.get(one).and_then(ClipboardEntry::text), Some("one two"));
+
+        // The id of an entry that isn't saved is not handed out again after a restart
+        let path = "/tmp/test_ring_buffer_ids.json";
+        let secret = buffer.push(ClipboardEntry::new("hunter2".to_string()).with_sensitive(true));
+        buffer.save_to_file(path).unwrap();
+        let mut reloaded = ClipboardBuffer::new_with_persistence(10, path).unwrap();
+        assert!(reloaded.get(secret).is_none());
+        assert!(reloaded.push(ClipboardEntry::new("new".to_string())) > secret);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_pinned_entries_are_kept() {
+        let mut buffer = ClipboardBuffer::new(2);
+        let address = buffer.push(ClipboardEntry::new("10.0.0.1".to_string()));
+        assert!(buffer.pin(address));
+
+        buffer.push(ClipboardEntry::new("a".to_string()));
+        buffer.push(ClipboardEntry::new("b".to_string()));
+        buffer.push(ClipboardEntry::new("c".to_string()));
+
+        // Two unpinned entries plus the pinned one
+        let texts: Vec<_> = buffer.entries().filter_map(ClipboardEntry::text).collect();
+        assert_eq!(texts, vec!["c", "b", "10.0.0.1"]);
+        assert_eq!(buffer.pinned().map(|e| e.id).collect::<Vec<_>>(), vec![address]);
+
+        // Coalescing never overwrites a pinned entry
+        buffer.move_to_front(address);
+        buffer.replace_newest(address, ClipboardEntry::new("d".to_string()));
+        assert_eq!(buffer.get(address).and_then(ClipboardEntry::text), Some("10.0.0.1"));
+
+        // Unpinned, it counts again, so the oldest entry goes
+        assert!(buffer.unpin(address));
+        let texts: Vec<_> = buffer.entries().filter_map(ClipboardEntry::text).collect();
+        assert_eq!(texts, vec!["d", "10.0.0.1"]);
+    }
+}
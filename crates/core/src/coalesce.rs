@@ -0,0 +1,249 @@
+/// Coalescing of rapid clipboard changes.
+///
+/// Editors that copy the selection as it is dragged out, or scripts copying
+/// in bulk, change the clipboard many times per second. A change from the same
+/// known owner within a short window updates that owner's entry instead of
+/// adding one. Owners exceeding a rate limit have their changes held back
+/// until they slow down; the latest one is then recorded.
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::buffer::{ClipboardEntry, Selection, SourceApp};
+use crate::config::CaptureConfig;
+
+/// What to do with a clipboard change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Add it as a new entry
+    Push,
+    /// Update the entry with this id, the one the previous change was recorded as
+    Replace(u64),
+    /// Ignore it for now; its owner is changing the clipboard too fast
+    Drop,
+}
+
+/// The last recorded change
+struct Last {
+    selection: Selection,
+    source: Option<SourceApp>,
+    at: Instant,
+    /// Entry it was recorded as, once known
+    id: Option<u64>,
+}
+
+/// Rate-limit state of one owner
+#[derive(Default)]
+struct Owner {
+    /// Times of the new entries it added within the last second
+    recent: VecDeque<Instant>,
+    /// Latest change dropped over the limit, recorded once the owner slows down
+    held: Option<ClipboardEntry>,
+}
+
+impl Owner {
+    fn prune(&mut self, now: Instant) {
+        while self.recent.front().is_some_and(|&at| now.duration_since(at) >= Duration::from_secs(1)) {
+            self.recent.pop_front();
+        }
+    }
+}
+
+pub struct Coalescer {
+    window: Duration,
+    max_per_second: usize,
+    last: Option<Last>,
+    /// Rate limits are kept per owner, so one runaway script doesn't drop
+    /// everyone's copies; unknown owners share one
+    owners: HashMap<Option<SourceApp>, Owner>,
+}
+
+impl Coalescer {
+    pub fn new(config: &CaptureConfig) -> Self {
+        Self {
+            window: Duration::from_millis(config.coalesce_ms),
+            max_per_second: config.max_changes_per_second as usize,
+            last: None,
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Decide how to record `entry`, captured at `now`
+    pub fn decide(&mut self, entry: &ClipboardEntry, now: Instant) -> Decision {
+        // Unrelated copies can't be told apart without an owner, so those are never merged
+        let replace = self
+            .last
+            .as_mut()
+            .filter(|last| {
+                entry.source.is_some()
+                    && last.source == entry.source
+                    && last.selection == entry.selection
+                    && now.duration_since(last.at) < self.window
+            })
+            .and_then(|last| {
+                last.at = now;
+                last.id
+            });
+        // Updates don't add entries, so they don't count toward the limit
+        if let Some(id) = replace {
+            return Decision::Replace(id);
+        }
+
+        self.owners.retain(|_, owner| {
+            owner.prune(now);
+            !owner.recent.is_empty() || owner.held.is_some()
+        });
+        let owner = self.owners.entry(entry.source.clone()).or_default();
+        if self.max_per_second > 0 && owner.recent.len() >= self.max_per_second {
+            if owner.held.is_none() {
+                eprintln!(
+                    "{} is changing the clipboard more than {} times per second; holding changes until it slows down",
+                    entry.source.as_ref().and_then(SourceApp::name).unwrap_or("An application"),
+                    self.max_per_second
+                );
+            }
+            owner.held = Some(entry.clone());
+            return Decision::Drop;
+        }
+        owner.recent.push_back(now);
+        // A newer change supersedes the one held back
+        owner.held = None;
+
+        self.last = Some(Last {
+            selection: entry.selection,
+            source: entry.source.clone(),
+            at: now,
+            id: None,
+        });
+        Decision::Push
+    }
+
+    /// Remember the id of the entry the last change was recorded as
+    pub fn recorded(&mut self, id: u64) {
+        if let Some(last) = self.last.as_mut() {
+            last.id = Some(id);
+        }
+    }
+
+    /// When a held-back change can next be released
+    pub fn next_release(&self) -> Option<Instant> {
+        self.owners
+            .values()
+            .filter(|owner| owner.held.is_some())
+            .filter_map(|owner| owner.recent.front().map(|&at| at + Duration::from_secs(1)))
+            .min()
+    }
+
+    /// Take a held-back change whose owner is back under the limit, to be passed to `decide` again
+    pub fn release(&mut self, now: Instant) -> Option<ClipboardEntry> {
+        let max_per_second = self.max_per_second;
+        self.owners.values_mut().find_map(|owner| {
+            owner.prune(now);
+            if owner.recent.len() < max_per_second || max_per_second == 0 {
+                owner.held.take()
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy(text: &str, source: Option<&SourceApp>) -> ClipboardEntry {
+        ClipboardEntry::new(text.to_string()).with_source(source.cloned())
+    }
+
+    fn app(name: &str) -> SourceApp {
+        SourceApp {
+            wm_class: Some(name.to_string()),
+            ..SourceApp::default()
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let config = CaptureConfig {
+            coalesce_ms: 100,
+            ..CaptureConfig::default()
+        };
+        let mut coalescer = Coalescer::new(&config);
+        let editor = app("editor");
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(coalescer.decide(&copy("hel", Some(&editor)), at(0)), Decision::Push);
+        coalescer.recorded(1);
+        assert_eq!(coalescer.decide(&copy("hello", Some(&editor)), at(50)), Decision::Replace(1));
+        // A selection that shrinks or moves while dragged out updates the entry too
+        assert_eq!(coalescer.decide(&copy("ell", Some(&editor)), at(100)), Decision::Replace(1));
+        // The window restarts with each update
+        assert_eq!(coalescer.decide(&copy("world", Some(&editor)), at(250)), Decision::Push);
+        coalescer.recorded(2);
+        // Another owner starts a new entry
+        assert_eq!(coalescer.decide(&copy("world", Some(&app("terminal"))), at(260)), Decision::Push);
+    }
+
+    #[test]
+    fn test_unrelated_copies_are_kept() {
+        let mut coalescer = Coalescer::new(&CaptureConfig::default());
+        let now = Instant::now();
+
+        // Without an owner, two quick copies may come from different applications
+        assert_eq!(coalescer.decide(&copy("hello", None), now), Decision::Push);
+        coalescer.recorded(1);
+        assert_eq!(coalescer.decide(&copy("world", None), now), Decision::Push);
+    }
+
+    #[test]
+    fn test_rate_limit_per_owner() {
+        let config = CaptureConfig {
+            coalesce_ms: 0,
+            max_changes_per_second: 2,
+            ..CaptureConfig::default()
+        };
+        let mut coalescer = Coalescer::new(&config);
+        let script = app("script");
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(coalescer.decide(&copy("1", Some(&script)), at(0)), Decision::Push);
+        assert_eq!(coalescer.decide(&copy("2", Some(&script)), at(10)), Decision::Push);
+        assert_eq!(coalescer.decide(&copy("3", Some(&script)), at(20)), Decision::Drop);
+        assert_eq!(coalescer.decide(&copy("4", Some(&script)), at(30)), Decision::Drop);
+
+        // Other applications are not held back by the script
+        assert_eq!(coalescer.decide(&copy("mine", Some(&app("editor"))), at(40)), Decision::Push);
+        assert_eq!(coalescer.decide(&copy("mine too", None), at(50)), Decision::Push);
+
+        // The trailing value is recorded once the script slows down
+        assert_eq!(coalescer.next_release(), Some(at(1000)));
+        assert!(coalescer.release(at(500)).is_none());
+        let held = coalescer.release(at(1000)).unwrap();
+        assert_eq!(held.text(), Some("4"));
+        assert_eq!(coalescer.decide(&held, at(1000)), Decision::Push);
+        assert_eq!(coalescer.next_release(), None);
+    }
+
+    #[test]
+    fn test_updates_are_not_rate_limited() {
+        let config = CaptureConfig {
+            coalesce_ms: 500,
+            max_changes_per_second: 2,
+            ..CaptureConfig::default()
+        };
+        let mut coalescer = Coalescer::new(&config);
+        let editor = app("editor");
+        let start = Instant::now();
+
+        assert_eq!(coalescer.decide(&copy("a", Some(&editor)), start), Decision::Push);
+        coalescer.recorded(1);
+        let mut text = String::from("a");
+        for ms in 1..50 {
+            text.push('a');
+            let decision = coalescer.decide(&copy(&text, Some(&editor)), start + Duration::from_millis(ms * 10));
+            assert_eq!(decision, Decision::Replace(1));
+        }
+    }
+}
@@ -6,6 +6,8 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 
+use crate::filter::FilterConfig;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Maximum number of clipboard entries to store
@@ -16,6 +18,22 @@ pub struct Config {
     #[serde(default = "default_max_entry_size")]
     pub max_entry_size: usize,
 
+    /// What to do with copies larger than `max_entry_size`
+    #[serde(default)]
+    pub on_oversized: OversizedAction,
+
+    /// Filters a change has to pass before it is recorded, in order
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+
+    /// Clipboard capture configuration
+    #[serde(default)]
+    pub capture: CaptureConfig,
+
+    /// Handling of passwords and other secrets
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+
     /// UI popup configuration
     #[serde(default)]
     pub ui: UiConfig,
@@ -25,6 +43,215 @@ pub struct Config {
     pub hotkey: HotkeyConfig,
 }
 
+/// What to do with a copy larger than `max_entry_size`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedAction {
+    /// Don't record it
+    #[default]
+    Skip,
+    /// Keep the first `max_entry_size` bytes of text; other content is skipped
+    Truncate,
+}
+
+/// Clipboard backend used to capture changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Pick from the session environment
+    #[default]
+    Auto,
+    X11,
+    Wayland,
+    /// Capture nothing
+    Null,
+    /// In-memory clipboard driven by the embedder, for tests and headless runs
+    Mock,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "x11" => Ok(Self::X11),
+            "wayland" => Ok(Self::Wayland),
+            "null" => Ok(Self::Null),
+            "mock" => Ok(Self::Mock),
+            _ => anyhow::bail!("Unknown backend: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureConfig {
+    /// Clipboard backend: "auto", "x11", "wayland", "null" or "mock"
+    #[serde(default)]
+    pub backend: Backend,
+
+    /// Also record the PRIMARY selection (highlighted text)
+    #[serde(default)]
+    pub primary: bool,
+
+    /// How long PRIMARY must stay unchanged before it is recorded, in milliseconds
+    #[serde(default = "default_primary_settle_ms")]
+    pub primary_settle_ms: u64,
+
+    /// X displays to watch, e.g. [":0", ":1"]; empty watches the session's own display
+    #[serde(default)]
+    pub displays: Vec<String>,
+
+    /// Act as the X11 clipboard manager: keep serving a copy after its application exits
+    #[serde(default = "default_clipboard_manager")]
+    pub clipboard_manager: bool,
+
+    /// Copy changes between PRIMARY and CLIPBOARD
+    #[serde(default)]
+    pub sync: SyncMode,
+
+    /// Don't sync PRIMARY while a mouse button is held, i.e. while a selection is being dragged out
+    #[serde(default = "default_sync_skip_while_held")]
+    pub sync_skip_while_held: bool,
+
+    /// Targets whose data is kept verbatim with each entry, for inspection
+    #[serde(default)]
+    pub raw_targets: Vec<String>,
+
+    /// Total size of the raw target data kept per entry, in bytes
+    #[serde(default = "default_raw_targets_budget")]
+    pub raw_targets_budget: usize,
+
+    /// Changes from the same owner within this many milliseconds update the newest entry; 0 disables
+    #[serde(default = "default_coalesce_ms")]
+    pub coalesce_ms: u64,
+
+    /// New entries beyond this many per second from one owner are held back; 0 disables the limit
+    #[serde(default = "default_max_changes_per_second")]
+    pub max_changes_per_second: u32,
+}
+
+impl CaptureConfig {
+    /// Whether PRIMARY has to be watched, for recording or for syncing
+    pub fn watches_primary(&self) -> bool {
+        self.primary || matches!(self.sync, SyncMode::PrimaryToClipboard | SyncMode::Both)
+    }
+}
+
+/// Which selection changes are copied to the other selection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMode {
+    #[default]
+    Off,
+    /// Highlighted text lands on Ctrl+V
+    PrimaryToClipboard,
+    /// Copied text lands on middle-click
+    ClipboardToPrimary,
+    Both,
+}
+
+/// What to do with a copy that carries a concealment hint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcealedAction {
+    /// Don't record it at all
+    #[default]
+    Skip,
+    /// Keep it in memory, masked in the popup, but never write it to disk
+    MarkSensitive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrivacyConfig {
+    /// Targets password managers offer to mark a copy as secret.
+    /// "name" matches when the target is offered, "name=value" when its data equals value
+    #[serde(default = "default_concealment_hints")]
+    pub concealment_hints: Vec<String>,
+
+    /// What to do with copies carrying one of the hints
+    #[serde(default)]
+    pub on_concealed: ConcealedAction,
+
+    /// Rules matched against the text of every copy before it is recorded
+    #[serde(default)]
+    pub rules: Vec<PrivacyRule>,
+
+    /// Built-in detectors for keys, tokens and card numbers
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+}
+
+/// A `[[privacy.rules]]` entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacyRule {
+    /// Regular expression searched for in the copied text
+    pub pattern: String,
+    pub action: RuleAction,
+    /// Seconds a matching entry is kept, for the `expire` action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<u64>,
+}
+
+/// What to do with a copy a secret detector matches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretAction {
+    /// Detector disabled
+    #[default]
+    Off,
+    /// Keep it in memory, masked in the popup, but never write it to disk
+    MarkSensitive,
+    /// Don't record it
+    Drop,
+}
+
+/// The `[privacy.secrets]` section: an action per detector, all off by default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    /// AWS access key ids and secret access keys
+    #[serde(default)]
+    pub aws: SecretAction,
+    /// GCP API keys and service account key files
+    #[serde(default)]
+    pub gcp: SecretAction,
+    #[serde(default)]
+    pub github: SecretAction,
+    #[serde(default)]
+    pub gitlab: SecretAction,
+    #[serde(default)]
+    pub jwt: SecretAction,
+    /// PEM-encoded private keys
+    #[serde(default)]
+    pub private_key: SecretAction,
+    /// OpenSSH and PuTTY private keys
+    #[serde(default)]
+    pub ssh_key: SecretAction,
+    /// Numbers passing the Luhn check, as payment cards do
+    #[serde(default)]
+    pub card_number: SecretAction,
+    /// Long random-looking words; hashes and ids can match too
+    #[serde(default)]
+    pub high_entropy: SecretAction,
+    /// Bits per character above which a word counts as high-entropy
+    #[serde(default = "default_entropy_threshold")]
+    pub entropy_threshold: f64,
+}
+
+/// What to do with a copy matching a privacy rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Don't record it
+    Drop,
+    /// Record it, but hide its content in the popup
+    Mask,
+    /// Keep it in memory only, never writing it to disk
+    NoPersist,
+    /// Remove it from the history after `expire_after` seconds
+    Expire,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UiConfig {
     /// Popup window width in pixels
@@ -34,6 +261,10 @@ pub struct UiConfig {
     /// Popup window height in pixels
     #[serde(default = "default_popup_height")]
     pub height: i32,
+
+    /// Show entries captured from the PRIMARY selection
+    #[serde(default = "default_show_primary")]
+    pub show_primary: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +283,41 @@ fn default_max_entry_size() -> usize {
     1048576 // 1MB
 }
 
+fn default_primary_settle_ms() -> u64 {
+    300
+}
+
+fn default_entropy_threshold() -> f64 {
+    4.0
+}
+
+fn default_concealment_hints() -> Vec<String> {
+    vec![
+        "x-kde-passwordManagerHint=secret".to_string(),
+        "application/x-nspasswd".to_string(),
+    ]
+}
+
+fn default_clipboard_manager() -> bool {
+    true
+}
+
+fn default_sync_skip_while_held() -> bool {
+    true
+}
+
+fn default_raw_targets_budget() -> usize {
+    65536 // 64KB
+}
+
+fn default_coalesce_ms() -> u64 {
+    500
+}
+
+fn default_max_changes_per_second() -> u32 {
+    20
+}
+
 fn default_popup_width() -> i32 {
     600
 }
@@ -60,6 +326,10 @@ fn default_popup_height() -> i32 {
     400
 }
 
+fn default_show_primary() -> bool {
+    true
+}
+
 fn default_hotkey() -> String {
     "Ctrl+Shift+V".to_string()
 }
@@ -69,17 +339,68 @@ impl Default for Config {
         Self {
             max_entries: default_max_entries(),
             max_entry_size: default_max_entry_size(),
+            on_oversized: OversizedAction::default(),
+            filters: Vec::new(),
+            capture: CaptureConfig::default(),
+            privacy: PrivacyConfig::default(),
             ui: UiConfig::default(),
             hotkey: HotkeyConfig::default(),
         }
     }
 }
 
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            primary: false,
+            primary_settle_ms: default_primary_settle_ms(),
+            displays: Vec::new(),
+            clipboard_manager: default_clipboard_manager(),
+            sync: SyncMode::default(),
+            sync_skip_while_held: default_sync_skip_while_held(),
+            raw_targets: Vec::new(),
+            raw_targets_budget: default_raw_targets_budget(),
+            coalesce_ms: default_coalesce_ms(),
+            max_changes_per_second: default_max_changes_per_second(),
+        }
+    }
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            concealment_hints: default_concealment_hints(),
+            on_concealed: ConcealedAction::default(),
+            rules: Vec::new(),
+            secrets: SecretsConfig::default(),
+        }
+    }
+}
+
+impl Default for SecretsConfig {
+    fn default() -> Self {
+        Self {
+            aws: SecretAction::default(),
+            gcp: SecretAction::default(),
+            github: SecretAction::default(),
+            gitlab: SecretAction::default(),
+            jwt: SecretAction::default(),
+            private_key: SecretAction::default(),
+            ssh_key: SecretAction::default(),
+            card_number: SecretAction::default(),
+            high_entropy: SecretAction::default(),
+            entropy_threshold: default_entropy_threshold(),
+        }
+    }
+}
+
 impl Default for UiConfig {
     fn default() -> Self {
         Self {
             width: default_popup_width(),
             height: default_popup_height(),
+            show_primary: default_show_primary(),
         }
     }
 }
@@ -223,5 +544,29 @@ mod tests {
         assert_eq!(config.max_entries, 100);
         assert_eq!(config.ui.width, 600);
         assert_eq!(config.hotkey.popup, "Ctrl+Shift+V");
+        assert!(!config.capture.primary);
+        assert!(config.ui.show_primary);
+        assert_eq!(config.privacy.on_concealed, ConcealedAction::Skip);
+        assert!(config.privacy.concealment_hints.contains(&"x-kde-passwordManagerHint=secret".to_string()));
+    }
+
+    #[test]
+    fn test_partial_capture_section() {
+        let config: Config = toml::from_str("[capture]\nprimary = true\n").unwrap();
+        assert!(config.capture.primary);
+        assert_eq!(config.capture.primary_settle_ms, 300);
+        assert_eq!(config.capture.backend, Backend::Auto);
+        assert!(config.capture.clipboard_manager);
+
+        let config: Config = toml::from_str("[capture]\nbackend = \"wayland\"\n").unwrap();
+        assert_eq!(config.capture.backend, Backend::Wayland);
+        assert_eq!(config.capture.sync, SyncMode::Off);
+        assert!(!config.capture.watches_primary());
+
+        let config: Config = toml::from_str("[capture]\nsync = \"primary_to_clipboard\"\n").unwrap();
+        assert!(config.capture.watches_primary());
+        assert!(config.capture.sync_skip_while_held);
+        assert_eq!("mock".parse::<Backend>().unwrap(), Backend::Mock);
+        assert!("gtk".parse::<Backend>().is_err());
     }
 }
\ No newline at end of file
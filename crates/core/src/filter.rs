@@ -0,0 +1,249 @@
+/// Content filters applied to clipboard changes before they are stored.
+///
+/// Filters run in order; each one accepts a change, rejects it with a
+/// reason, or rewrites it for the filters after it.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::{ClipboardContent, ClipboardEntry};
+use crate::config::{Config, OversizedAction};
+use crate::privacy::PrivacyRules;
+use crate::secrets::SecretDetectors;
+
+/// Outcome of running a filter on an entry
+#[derive(Debug, Clone)]
+pub enum Verdict {
+    Accept,
+    /// Store this version of the entry instead
+    Rewrite(Box<ClipboardEntry>),
+    /// Don't store the entry, for the given reason
+    Reject(String),
+}
+
+pub trait Filter: Send + Sync {
+    /// Short name used in log messages
+    fn name(&self) -> &str;
+
+    fn check(&self, entry: &ClipboardEntry) -> Verdict;
+}
+
+/// A filter in the `[[filters]]` config list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilterConfig {
+    /// Text shorter than `min` or longer than `max` characters
+    Length {
+        #[serde(default)]
+        min: Option<usize>,
+        #[serde(default)]
+        max: Option<usize>,
+    },
+    /// Text that is empty or only whitespace
+    Whitespace,
+    /// Text that is a single character, ignoring surrounding whitespace
+    SingleChar,
+    /// Text with more than `max` lines
+    MaxLines { max: usize },
+}
+
+/// Ordered list of filters an entry has to pass
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl FilterChain {
+    /// Privacy rules and secret detectors, so they see the whole copy, then
+    /// the size limit from `max_entry_size` and the `[[filters]]` list
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut chain = Self::default();
+        let rules = PrivacyRules::from_config(&config.privacy)?;
+        if !rules.is_empty() {
+            chain.push(rules);
+        }
+        let secrets = SecretDetectors::from_config(&config.privacy.secrets);
+        if !secrets.is_empty() {
+            chain.push(secrets);
+        }
+        chain.push(MaxSize {
+            max: config.max_entry_size,
+            on_oversized: config.on_oversized,
+        });
+        for filter in &config.filters {
+            match filter.clone() {
+                FilterConfig::Length { min, max } => chain.push(Length { min, max }),
+                FilterConfig::Whitespace => chain.push(Whitespace),
+                FilterConfig::SingleChar => chain.push(SingleChar),
+                FilterConfig::MaxLines { max } => chain.push(MaxLines { max }),
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Add a filter at the end of the chain
+    pub fn push(&mut self, filter: impl Filter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Run `entry` through every filter, returning it as rewritten, or `None`
+    /// if a filter rejected it. Rejections are logged with their reason.
+    pub fn apply(&self, mut entry: ClipboardEntry) -> Option<ClipboardEntry> {
+        for filter in &self.filters {
+            match filter.check(&entry) {
+                Verdict::Accept => {}
+                Verdict::Rewrite(rewritten) => entry = *rewritten,
+                Verdict::Reject(reason) => {
+                    eprintln!("Not recording clipboard change ({} filter): {}", filter.name(), reason);
+                    return None;
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// Enforces `max_entry_size` on content that didn't come through a size-limited capture
+struct MaxSize {
+    max: usize,
+    on_oversized: OversizedAction,
+}
+
+impl Filter for MaxSize {
+    fn name(&self) -> &str {
+        "max_entry_size"
+    }
+
+    fn check(&self, entry: &ClipboardEntry) -> Verdict {
+        let size = entry.content.size();
+        if size <= self.max {
+            return Verdict::Accept;
+        }
+
+        match (&entry.content, self.on_oversized) {
+            (ClipboardContent::Text { text, .. }, OversizedAction::Truncate) => {
+                let mut end = self.max.min(text.len());
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                // The formatted versions would no longer match the cut text
+                Verdict::Rewrite(Box::new(ClipboardEntry {
+                    content: ClipboardContent::from(text[..end].to_string()),
+                    ..entry.clone()
+                }))
+            }
+            _ => Verdict::Reject(format!("{} bytes is over the limit of {}", size, self.max)),
+        }
+    }
+}
+
+struct Length {
+    min: Option<usize>,
+    max: Option<usize>,
+}
+
+impl Filter for Length {
+    fn name(&self) -> &str {
+        "length"
+    }
+
+    fn check(&self, entry: &ClipboardEntry) -> Verdict {
+        let Some(text) = entry.text() else {
+            return Verdict::Accept;
+        };
+
+        let length = text.chars().count();
+        if let Some(min) = self.min
+            && length < min
+        {
+            return Verdict::Reject(format!("{} characters is shorter than {}", length, min));
+        }
+        if let Some(max) = self.max
+            && length > max
+        {
+            return Verdict::Reject(format!("{} characters is longer than {}", length, max));
+        }
+        Verdict::Accept
+    }
+}
+
+struct Whitespace;
+
+impl Filter for Whitespace {
+    fn name(&self) -> &str {
+        "whitespace"
+    }
+
+    fn check(&self, entry: &ClipboardEntry) -> Verdict {
+        match entry.text() {
+            Some(text) if text.trim().is_empty() => Verdict::Reject("only whitespace".to_string()),
+            _ => Verdict::Accept,
+        }
+    }
+}
+
+struct SingleChar;
+
+impl Filter for SingleChar {
+    fn name(&self) -> &str {
+        "single_char"
+    }
+
+    fn check(&self, entry: &ClipboardEntry) -> Verdict {
+        match entry.text() {
+            Some(text) if text.trim().chars().count() == 1 => Verdict::Reject("a single character".to_string()),
+            _ => Verdict::Accept,
+        }
+    }
+}
+
+struct MaxLines {
+    max: usize,
+}
+
+impl Filter for MaxLines {
+    fn name(&self) -> &str {
+        "max_lines"
+    }
+
+    fn check(&self, entry: &ClipboardEntry) -> Verdict {
+        match entry.text().map(|text| text.lines().count()) {
+            Some(lines) if lines > self.max => Verdict::Reject(format!("{} lines is more than {}", lines, self.max)),
+            _ => Verdict::Accept,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_chain() {
+        let config: Config = toml::from_str(
+            r#"
+            max_entry_size = 10
+            on_oversized = "truncate"
+
+            [[filters]]
+            type = "whitespace"
+
+            [[filters]]
+            type = "single_char"
+
+            [[filters]]
+            type = "max_lines"
+            max = 2
+            "#,
+        )
+        .unwrap();
+        let chain = FilterChain::from_config(&config).unwrap();
+        let apply = |text: &str| chain.apply(ClipboardEntry::new(text.to_string())).map(|e| e.content);
+
+        assert_eq!(apply("hello"), Some(ClipboardContent::from("hello".to_string())));
+        assert_eq!(apply(" \n\t"), None);
+        assert_eq!(apply(" x "), None);
+        assert_eq!(apply("a\nb\nc"), None);
+        // Truncated to max_entry_size before the other filters see it
+        assert_eq!(apply("hello world!"), Some(ClipboardContent::from("hello worl".to_string())));
+    }
+}
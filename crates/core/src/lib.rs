@@ -1,3 +1,7 @@
 pub mod buffer;
+pub mod coalesce;
 pub mod config;
-pub mod persistence;
\ No newline at end of file
+pub mod filter;
+pub mod persistence;
+pub mod privacy;
+pub mod secrets;
\ No newline at end of file
@@ -3,19 +3,33 @@ use std::io::{BufReader, BufWriter};
 use std::path::Path;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 use crate::buffer::ClipboardEntry;
 
 #[derive(Serialize, Deserialize)]
-struct PersistedData {
+struct PersistedData<T> {
     version: u32,
-    entries: Vec<ClipboardEntry>,
+    /// Id the next new entry gets; ids of entries left out of the file are never reused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_id: Option<u64>,
+    entries: Vec<T>,
 }
 
-const CURRENT_VERSION: u32 = 1;
+/// Version 2 replaced the plain `text` field with a tagged `content` object.
+/// Version 3 added stable entry ids; older entries are given one on load.
+/// Version 4 added `next_id`.
+const CURRENT_VERSION: u32 = 4;
 
-/// Save clipboard entries to a JSON file
-pub fn save_to_file<P: AsRef<Path>>(path: P, entries: &[ClipboardEntry]) -> Result<()> {
+/// History read back from a file
+pub struct History {
+    pub entries: Vec<ClipboardEntry>,
+    /// Id the next new entry gets, if the file records it
+    pub next_id: Option<u64>,
+}
+
+/// Save clipboard entries to a JSON file, leaving out sensitive and transient ones
+pub fn save_to_file<P: AsRef<Path>>(path: P, entries: &[ClipboardEntry], next_id: u64) -> Result<()> {
     let path = path.as_ref();
 
     // Create parent directory if it doesn't exist
@@ -26,7 +40,8 @@ pub fn save_to_file<P: AsRef<Path>>(path: P, entries: &[ClipboardEntry]) -> Resu
 
     let data = PersistedData {
         version: CURRENT_VERSION,
-        entries: entries.to_vec(),
+        next_id: Some(next_id),
+        entries: entries.iter().filter(|e| !e.is_transient()).cloned().collect(),
     };
 
     let file = File::create(path)
@@ -40,35 +55,60 @@ pub fn save_to_file<P: AsRef<Path>>(path: P, entries: &[ClipboardEntry]) -> Resu
 }
 
 /// Load clipboard entries from a JSON file
-pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Vec<ClipboardEntry>> {
+pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<History> {
     let path = path.as_ref();
 
     if !path.exists() {
         // File doesn't exist yet, return empty vec (not an error)
-        return Ok(Vec::new());
+        return Ok(History {
+            entries: Vec::new(),
+            next_id: None,
+        });
     }
 
     let file = File::open(path)
         .context("Failed to open persistence file")?;
     let reader = BufReader::new(file);
 
-    let data: PersistedData = serde_json::from_reader(reader)
+    let data: PersistedData<Value> = serde_json::from_reader(reader)
         .context("Failed to deserialize clipboard data")?;
 
-    // Future: handle version migrations here
-    if data.version != CURRENT_VERSION {
-        // For now, just log a warning and proceed
+    if data.version > CURRENT_VERSION {
+        // Written by a newer version, try to read it anyway
         eprintln!("Warning: persistence file version mismatch (expected {}, got {})",
                  CURRENT_VERSION, data.version);
     }
 
-    Ok(data.entries)
+    let entries = data.entries
+        .into_iter()
+        .map(|entry| {
+            let entry = migrate_entry(entry, data.version);
+            serde_json::from_value(entry).context("Failed to deserialize clipboard entry")
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(History {
+        entries,
+        next_id: data.next_id,
+    })
+}
+
+/// Upgrade a raw entry written by an older version to the current format
+fn migrate_entry(mut entry: Value, version: u32) -> Value {
+    if version < 2
+        && let Some(fields) = entry.as_object_mut()
+        && let Some(text) = fields.remove("text")
+    {
+        fields.insert("content".to_string(), json!({ "kind": "text", "text": text }));
+    }
+
+    entry
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::buffer::ClipboardEntry;
+    use crate::buffer::{ClipboardEntry, SourceApp};
 
     #[test]
     fn test_save_and_load() {
@@ -76,15 +116,40 @@ mod tests {
 
         let entries = vec![
             ClipboardEntry::new("Hello".to_string()),
-            ClipboardEntry::new("World".to_string()),
+            ClipboardEntry::new("World".to_string()).with_source(Some(SourceApp {
+                wm_class: Some("firefox".to_string()),
+                pid: Some(4242),
+                executable: Some("firefox-bin".to_string()),
+            })).with_display(Some(":1".to_string())),
+            ClipboardEntry::new("hunter2".to_string()).with_sensitive(true),
         ];
 
-        save_to_file(temp_path, &entries).unwrap();
-        let loaded = load_from_file(temp_path).unwrap();
+        save_to_file(temp_path, &entries, 7).unwrap();
+        let History { entries: loaded, next_id } = load_from_file(temp_path).unwrap();
+
+        // The id of the sensitive entry left out is not handed out again
+        assert_eq!(next_id, Some(7));
 
         assert_eq!(loaded.len(), 2);
-        assert_eq!(loaded[0].text, "Hello");
-        assert_eq!(loaded[1].text, "World");
+        assert_eq!(loaded[0].text(), Some("Hello"));
+        assert_eq!(loaded[1].text(), Some("World"));
+        assert_eq!(loaded[0].source, None);
+        assert_eq!(loaded[1].source.as_ref().and_then(SourceApp::name), Some("firefox"));
+        assert_eq!(loaded[1].display.as_deref(), Some(":1"));
+
+        std::fs::remove_file(temp_path).ok();
+    }
+
+    #[test]
+    fn test_load_version_1() {
+        let temp_path = "/tmp/test_clipboard_history_v1.json";
+        let v1 = r#"{"version": 1, "entries": [{"text": "Hello", "timestamp": 1700000000}]}"#;
+        std::fs::write(temp_path, v1).unwrap();
+
+        let loaded = load_from_file(temp_path).unwrap().entries;
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].text(), Some("Hello"));
 
         std::fs::remove_file(temp_path).ok();
     }
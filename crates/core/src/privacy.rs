@@ -0,0 +1,149 @@
+/// Privacy rules from `[[privacy.rules]]`, matched against copied text.
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+
+use crate::buffer::ClipboardEntry;
+use crate::config::{PrivacyConfig, RuleAction};
+use crate::filter::{Filter, Verdict};
+
+struct Rule {
+    regex: Regex,
+    action: RuleAction,
+    expire_after: Duration,
+}
+
+/// Filter applying every matching rule to an entry
+pub struct PrivacyRules {
+    rules: Vec<Rule>,
+}
+
+impl PrivacyRules {
+    /// Compile the rules, failing on invalid patterns and `expire` rules without `expire_after`
+    pub fn from_config(config: &PrivacyConfig) -> Result<Self> {
+        let mut rules = Vec::with_capacity(config.rules.len());
+        for rule in &config.rules {
+            let regex = Regex::new(&rule.pattern)
+                .with_context(|| format!("Invalid privacy rule pattern `{}`", rule.pattern))?;
+            let expire_after = match (rule.action, rule.expire_after) {
+                (RuleAction::Expire, Some(secs)) => Duration::from_secs(secs),
+                (RuleAction::Expire, None) => {
+                    bail!("Privacy rule `{}` expires entries but has no expire_after", rule.pattern)
+                }
+                _ => Duration::ZERO,
+            };
+            rules.push(Rule {
+                regex,
+                action: rule.action,
+                expire_after,
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl Filter for PrivacyRules {
+    fn name(&self) -> &str {
+        "privacy rules"
+    }
+
+    fn check(&self, entry: &ClipboardEntry) -> Verdict {
+        let Some(text) = entry.text() else {
+            return Verdict::Accept;
+        };
+
+        let mut matched = None;
+        for rule in self.rules.iter().filter(|rule| rule.regex.is_match(text)) {
+            match rule.action {
+                // Never log the text itself; it is what the rule keeps out
+                RuleAction::Drop => return Verdict::Reject(format!("matches `{}`", rule.regex.as_str())),
+                RuleAction::Mask | RuleAction::NoPersist => {
+                    let entry = matched.get_or_insert_with(|| entry.clone());
+                    match rule.action {
+                        RuleAction::Mask => entry.masked = true,
+                        _ => entry.transient = true,
+                    }
+                    // The raw data holds the same text
+                    entry.raw_targets.clear();
+                }
+                RuleAction::Expire => {
+                    let entry = matched.get_or_insert_with(|| entry.clone());
+                    let expires_at = (entry.timestamp + rule.expire_after)
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_secs());
+                    // The shortest matching rule wins
+                    entry.expires_at = Some(entry.expires_at.map_or(expires_at, |e| e.min(expires_at)));
+                }
+            }
+        }
+
+        match matched {
+            Some(entry) => Verdict::Rewrite(Box::new(entry)),
+            None => Verdict::Accept,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::RawTarget;
+    use crate::config::Config;
+
+    #[test]
+    fn test_privacy_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            [[privacy.rules]]
+            pattern = "^tok_[a-z0-9]+$"
+            action = "drop"
+
+            [[privacy.rules]]
+            pattern = "CUST-\\d+"
+            action = "mask"
+
+            [[privacy.rules]]
+            pattern = "CUST-\\d+"
+            action = "no_persist"
+
+            [[privacy.rules]]
+            pattern = "otp"
+            action = "expire"
+            expire_after = 30
+            "#,
+        )
+        .unwrap();
+        let rules = PrivacyRules::from_config(&config.privacy).unwrap();
+
+        let check = |text: &str| rules.check(&ClipboardEntry::new(text.to_string()));
+        assert!(matches!(check("tok_abc123"), Verdict::Reject(_)));
+        assert!(matches!(check("hello"), Verdict::Accept));
+
+        let customer = ClipboardEntry::new("order for CUST-42".to_string()).with_raw_targets(vec![RawTarget {
+            target: "UTF8_STRING".to_string(),
+            data: b"order for CUST-42".to_vec(),
+        }]);
+        let Verdict::Rewrite(entry) = rules.check(&customer) else {
+            panic!("expected a rewrite");
+        };
+        assert!(entry.is_masked() && entry.is_transient());
+        assert!(entry.raw_targets.is_empty());
+        assert_eq!(entry.expires_at, None);
+
+        let Verdict::Rewrite(entry) = check("otp 123456") else {
+            panic!("expected a rewrite");
+        };
+        assert!(!entry.is_masked());
+        assert!(!entry.is_expired(entry.timestamp));
+        assert!(entry.is_expired(entry.timestamp + Duration::from_secs(30)));
+
+        let missing_duration: Config =
+            toml::from_str("[[privacy.rules]]\npattern = \"x\"\naction = \"expire\"\n").unwrap();
+        assert!(PrivacyRules::from_config(&missing_duration.privacy).is_err());
+    }
+}
@@ -1,25 +1,101 @@
+use std::borrow::Cow;
 use std::sync::{Arc, Mutex};
 use zbus::{interface, Connection, Result};
-use core_lib::buffer::ClipboardBuffer;
+use clipboard::{ClipboardWriter, ListenerHandle, ListenerState, Writers};
+use core_lib::buffer::{ClipboardBuffer, ClipboardContent, ClipboardEntry, Selection};
+use core_lib::config::Config;
 
 /// D-Bus interface for clipboard history service
 pub struct ClipboardHistoryService {
     buffer: Arc<Mutex<ClipboardBuffer>>,
+    writers: Writers,
+    listener: Arc<ListenerHandle>,
 }
 
 impl ClipboardHistoryService {
-    pub fn new(buffer: Arc<Mutex<ClipboardBuffer>>) -> Self {
-        Self { buffer }
+    pub fn new(
+        buffer: Arc<Mutex<ClipboardBuffer>>,
+        writers: Writers,
+        listener: Arc<ListenerHandle>,
+    ) -> Self {
+        Self { buffer, writers, listener }
+    }
+
+    /// Writer for X `display`, or for the daemon's own display if `None`
+    fn writer(&self, display: Option<&str>) -> Option<&Arc<dyn ClipboardWriter>> {
+        if let Some(display) = display
+            && let Some(writer) = self.writers.get(&Some(display.to_string()))
+        {
+            return Some(writer);
+        }
+
+        // Without configured displays there is a single writer for the session
+        self.writers.get(&None).or_else(|| match display {
+            Some(_) => None,
+            None => self.writers.get(&std::env::var("DISPLAY").ok()),
+        })
+    }
+
+    /// Apply `f` to the history and persist it if `f` reports a change
+    fn modify(&self, f: impl FnOnce(&mut ClipboardBuffer) -> bool) -> bool {
+        let mut buffer = self.buffer.lock().unwrap();
+        if !f(&mut buffer) {
+            return false;
+        }
+
+        if let Err(e) = buffer.save_to_file(Config::storage_path()) {
+            eprintln!("Failed to save clipboard history: {}", e);
+        }
+        true
+    }
+
+    /// Put the entry with `id` on `selection` of `display`, served by the daemon
+    fn set_selection(&self, id: u64, selection: Selection, display: Option<&str>) -> bool {
+        let Some(writer) = self.writer(display) else {
+            eprintln!("No clipboard writer for display {}", display.unwrap_or("(default)"));
+            return false;
+        };
+
+        // Clone so the buffer isn't locked while taking the selection
+        let content = {
+            let buffer = self.buffer.lock().unwrap();
+            match buffer.get(id) {
+                Some(entry) => entry.content.clone(),
+                None => return false,
+            }
+        };
+
+        match writer.set(selection, &content) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Failed to set clipboard: {:#}", e);
+                false
+            }
+        }
     }
 }
 
 #[interface(name = "com.clipboardhistory.Service")]
 impl ClipboardHistoryService {
-    /// Get all clipboard entries as a JSON string
-    /// Returns: JSON array of entries with format: [{"text": "...", "timestamp": 123456789}, ...]
+    /// Get all clipboard entries as a JSON string, most recent first
+    /// Returns: JSON array of entries with format:
+    /// [{"id": 42, "content": {"kind": "text", "text": "...", "html": "...", "rtf": "..."}, "timestamp": 123456789, "selection": "clipboard"}, ...]
+    ///
+    /// "id" identifies the entry in the other methods and stays the same as new
+    /// entries arrive. Text content only has "html" and "rtf" when the owner
+    /// offered those formats; image content is {"kind": "image", "mime_type":
+    /// "image/png", "width": 800, "height": 600, "size": 1234, "data": "<base64>"}
+    /// and file content is {"kind": "files", "operation": "copy" | "cut",
+    /// "paths": [...]}. Entries may carry the "source" application ({"wm_class",
+    /// "pid", "executable"}, each optional), the X "display" they were copied on,
+    /// "pinned": true if they are never evicted (see PinEntry), "transient": true
+    /// if they are never persisted and "expires_at" if a privacy rule removes them
+    /// at that time. Entries marked "sensitive" by a password manager or secret
+    /// detector, or "masked" by a privacy rule, have their content replaced by
+    /// empty text; SetClipboard still pastes the original.
     fn get_entries(&self) -> String {
         let buffer = self.buffer.lock().unwrap();
-        let entries = buffer.entries_vec();
+        let entries: Vec<_> = buffer.entries().map(redact).collect();
 
         serde_json::to_string(&entries).unwrap_or_else(|_| "[]".to_string())
     }
@@ -30,19 +106,102 @@ impl ClipboardHistoryService {
         buffer.len() as u32
     }
 
-    /// Get a specific entry by index (0 = most recent)
-    /// Returns: JSON object or empty string if index out of bounds
-    fn get_entry(&self, index: u32) -> String {
+    /// Get a specific entry by id
+    /// Returns: JSON object or empty string if there is no such entry
+    fn get_entry(&self, id: u64) -> String {
         let buffer = self.buffer.lock().unwrap();
-        let entries = buffer.entries_vec();
 
-        if let Some(entry) = entries.get(index as usize) {
-            serde_json::to_string(entry).unwrap_or_default()
+        if let Some(entry) = buffer.get(id) {
+            serde_json::to_string(&redact(entry)).unwrap_or_default()
         } else {
             String::new()
         }
     }
 
+    /// Remove the entry with `id` from the history
+    /// Returns: false if there is no such entry
+    fn remove_entry(&self, id: u64) -> bool {
+        self.modify(|buffer| buffer.remove(id).is_some())
+    }
+
+    /// Pin the entry with `id`, so it is never evicted from the history
+    /// Returns: false if there is no such entry
+    fn pin_entry(&self, id: u64) -> bool {
+        self.modify(|buffer| buffer.pin(id))
+    }
+
+    /// Unpin the entry with `id`; it is evicted like any other entry again
+    /// Returns: false if there is no such entry
+    fn unpin_entry(&self, id: u64) -> bool {
+        self.modify(|buffer| buffer.unpin(id))
+    }
+
+    /// Get the pinned entries as a JSON string, in the same format as GetEntries
+    fn get_pinned_entries(&self) -> String {
+        let buffer = self.buffer.lock().unwrap();
+        let pinned: Vec<_> = buffer.pinned().map(redact).collect();
+
+        serde_json::to_string(&pinned).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Describe everything the owner offered for the entry with `id`
+    /// Returns: JSON object or empty string if there is no such entry:
+    /// {"summary": "...", "selection": "clipboard", "source": {...} | null, "display": ":0" | null,
+    ///  "targets": ["TARGETS", "UTF8_STRING", ...], "raw_targets": [{"target": "...", "size": 42, "data": "<base64>"}, ...]}
+    /// "raw_targets" only holds the targets listed in the capture.raw_targets setting; masked entries have no "data"
+    fn inspect_entry(&self, id: u64) -> String {
+        let buffer = self.buffer.lock().unwrap();
+        match buffer.get(id) {
+            Some(entry) => inspect(entry).to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Put the entry with `id` on the clipboard, offered in every format it has
+    /// Returns: false if there is no such entry or the clipboard could not be set
+    fn set_clipboard(&self, id: u64) -> bool {
+        self.set_selection(id, Selection::Clipboard, None)
+    }
+
+    /// Put the entry with `id` on the clipboard of X display `display`, e.g. ":1"
+    /// Returns: false if there is no such entry, the display is not watched or the clipboard could not be set
+    fn set_clipboard_on_display(&self, id: u64, display: &str) -> bool {
+        self.set_selection(id, Selection::Clipboard, Some(display))
+    }
+
+    /// Put the entry with `id` on the PRIMARY selection (middle-click paste)
+    /// Returns: false if there is no such entry or the selection could not be set
+    fn set_primary(&self, id: u64) -> bool {
+        self.set_selection(id, Selection::Primary, None)
+    }
+
+    /// Get the health of the clipboard listeners as a JSON string, one per watched display
+    /// Returns: [{"display": ":1" | null, "state": "starting" | "running" | "reconnecting" | "stopped", "last_error": "..." | null, "restarts": 0}, ...]
+    fn get_listener_health(&self) -> String {
+        let listeners: Vec<_> = self
+            .listener
+            .health()
+            .into_iter()
+            .map(|health| {
+                let state = match health.state {
+                    ListenerState::Starting => "starting",
+                    ListenerState::Running => "running",
+                    ListenerState::Reconnecting => "reconnecting",
+                    ListenerState::Stopped => "stopped",
+                };
+
+                serde_json::json!({
+                    "display": health.display,
+                    "state": state,
+                    "last_error": health.last_error,
+                    "restarts": health.restarts,
+                })
+            })
+            .collect();
+
+        serde_json::Value::from(listeners).to_string()
+    }
+
     /// Clear all clipboard history
     fn clear(&self) -> bool {
         // This would require adding a clear method to ClipboardBuffer
@@ -51,9 +210,58 @@ impl ClipboardHistoryService {
     }
 }
 
+/// `entry` as returned over D-Bus, without the content of masked entries
+fn redact(entry: &ClipboardEntry) -> Cow<'_, ClipboardEntry> {
+    if !entry.is_masked() {
+        return Cow::Borrowed(entry);
+    }
+    Cow::Owned(ClipboardEntry {
+        content: ClipboardContent::from(String::new()),
+        raw_targets: Vec::new(),
+        ..entry.clone()
+    })
+}
+
+/// Description of `entry` returned by InspectEntry
+fn inspect(entry: &ClipboardEntry) -> serde_json::Value {
+    // Keep secrets out of terminals and logs
+    let masked = entry.is_masked();
+    let raw_targets: Vec<_> = entry
+        .raw_targets
+        .iter()
+        .map(|raw| {
+            let mut value = serde_json::to_value(raw).unwrap_or_default();
+            value["size"] = raw.data.len().into();
+            if masked && let Some(value) = value.as_object_mut() {
+                value.remove("data");
+            }
+            value
+        })
+        .collect();
+
+    let summary = if masked {
+        "Sensitive entry".to_string()
+    } else {
+        entry.content.summary()
+    };
+
+    serde_json::json!({
+        "summary": summary,
+        "selection": entry.selection,
+        "source": entry.source,
+        "display": entry.display,
+        "targets": entry.targets,
+        "raw_targets": raw_targets,
+    })
+}
+
 /// Start the D-Bus service on the session bus
-pub async fn start_dbus_service(buffer: Arc<Mutex<ClipboardBuffer>>) -> Result<Connection> {
-    let service = ClipboardHistoryService::new(buffer);
+pub async fn start_dbus_service(
+    buffer: Arc<Mutex<ClipboardBuffer>>,
+    writers: Writers,
+    listener: Arc<ListenerHandle>,
+) -> Result<Connection> {
+    let service = ClipboardHistoryService::new(buffer, writers, listener);
 
     let connection = Connection::session().await?;
 
@@ -69,4 +277,29 @@ pub async fn start_dbus_service(buffer: Arc<Mutex<ClipboardBuffer>>) -> Result<C
     println!("D-Bus service started: com.clipboardhistory.Service");
 
     Ok(connection)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_lib::buffer::RawTarget;
+
+    #[test]
+    fn test_inspect_masked_entry() {
+        let mut entry = ClipboardEntry::new("CUST-42".to_string()).with_raw_targets(vec![RawTarget {
+            target: "UTF8_STRING".to_string(),
+            data: b"CUST-42".to_vec(),
+        }]);
+        entry.masked = true;
+
+        let inspected = inspect(&entry);
+        assert_eq!(inspected["summary"], "Sensitive entry");
+        assert_eq!(inspected["raw_targets"][0]["size"], 7);
+        assert!(inspected["raw_targets"][0].get("data").is_none());
+        assert!(!inspected.to_string().contains("CUST-42"));
+
+        let listed = serde_json::to_string(&redact(&entry)).unwrap();
+        assert!(listed.contains(r#""masked":true"#));
+        assert!(!listed.contains("CUST-42"));
+    }
+}
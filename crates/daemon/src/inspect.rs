@@ -0,0 +1,77 @@
+use anyhow::{bail, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::Value;
+
+/// Longest raw target preview printed, in bytes
+const PREVIEW_LEN: usize = 200;
+
+/// `inspect` subcommand: print what the application behind a history entry offered.
+///
+/// Inspects the most recent entry when no `id` is given.
+pub async fn run(id: Option<u64>) -> Result<()> {
+    let connection = zbus::Connection::session().await?;
+    let proxy = zbus::Proxy::new(
+        &connection,
+        "com.clipboardhistory.Service",
+        "/com/clipboardhistory/Service",
+        "com.clipboardhistory.Service",
+    )
+    .await?;
+
+    let id = match id {
+        Some(id) => id,
+        None => {
+            let entries: String = proxy.call("GetEntries", &()).await?;
+            let entries: Value = serde_json::from_str(&entries)?;
+            match entries[0]["id"].as_u64() {
+                Some(id) => id,
+                None => bail!("The clipboard history is empty"),
+            }
+        }
+    };
+
+    let json: String = proxy.call("InspectEntry", &(id,)).await?;
+    if json.is_empty() {
+        bail!("No entry with id {}", id);
+    }
+    let entry: Value = serde_json::from_str(&json)?;
+
+    println!("Entry {}: {}", id, entry["summary"].as_str().unwrap_or_default());
+    println!("Selection: {}", entry["selection"].as_str().unwrap_or("clipboard"));
+    if let Some(source) = entry["source"].as_object() {
+        let field = |name: &str| source.get(name).filter(|v| !v.is_null()).map(Value::to_string);
+        let parts: Vec<String> = [("WM_CLASS", field("wm_class")), ("PID", field("pid")), ("executable", field("executable"))]
+            .into_iter()
+            .filter_map(|(label, value)| value.map(|value| format!("{} {}", label, value)))
+            .collect();
+        println!("Source: {}", parts.join(", "));
+    }
+    if let Some(display) = entry["display"].as_str() {
+        println!("Display: {}", display);
+    }
+
+    let targets = entry["targets"].as_array().cloned().unwrap_or_default();
+    println!("Targets ({}):", targets.len());
+    for target in &targets {
+        println!("  {}", target.as_str().unwrap_or_default());
+    }
+
+    let raw_targets = entry["raw_targets"].as_array().cloned().unwrap_or_default();
+    if !raw_targets.is_empty() {
+        println!("Raw targets:");
+    }
+    for raw in &raw_targets {
+        let target = raw["target"].as_str().unwrap_or_default();
+        let Some(data) = raw["data"].as_str() else {
+            println!("  {} ({} bytes): hidden", target, raw["size"]);
+            continue;
+        };
+        let data = STANDARD.decode(data)?;
+        let preview = data[..data.len().min(PREVIEW_LEN)].escape_ascii().to_string();
+        let more = if data.len() > PREVIEW_LEN { "…" } else { "" };
+        println!("  {} ({} bytes): {}{}", target, data.len(), preview, more);
+    }
+
+    Ok(())
+}
@@ -1,29 +1,50 @@
 mod dbus_service;
+mod inspect;
 
 use anyhow::Result;
-use clipboard::x11::X11ClipboardListener;
-use core_lib::buffer::{ClipboardBuffer, ClipboardEntry};
-use core_lib::config::Config;
+use clipboard::{ClipboardEvent, MockClipboard};
+use core_lib::buffer::{ClipboardBuffer, ClipboardEntry, Selection};
+use core_lib::coalesce::{Coalescer, Decision};
+use core_lib::config::{Backend, Config};
+use core_lib::filter::FilterChain;
+use std::io::BufRead;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
-use clipboard::ClipboardListener;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
 use global_hotkey::{GlobalHotKeyManager, GlobalHotKeyEvent, HotKeyState, hotkey::{HotKey, Modifiers, Code}};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "inspect") {
+        let id = args.get(1).map(|id| id.parse()).transpose()?;
+        return inspect::run(id).await;
+    }
+
     // Load configuration
-    let config = Config::load()?;
+    let mut config = Config::load()?;
     Config::ensure_default_config()?;
+    apply_args(&mut config, args.into_iter())?;
 
     let storage_path = Config::storage_path();
 
     println!("Configuration:");
     println!("  Max entries: {}", config.max_entries);
     println!("  Hotkey: {}", config.hotkey.popup);
+    println!("  Backend: {:?}", config.capture.backend);
+    println!("  Capture PRIMARY: {}", config.capture.primary);
+    println!("  Selection sync: {:?}", config.capture.sync);
+    if !config.capture.displays.is_empty() {
+        println!("  Displays: {}", config.capture.displays.join(", "));
+    }
     println!("  Storage: {}", storage_path.display());
     println!();
 
+    // Checked before anything starts, so an invalid rule can't let copies through
+    let filters = FilterChain::from_config(&config)?;
+
     // Load existing history from persistence
     let buffer = match ClipboardBuffer::new_with_persistence(config.max_entries, &storage_path) {
         Ok(buf) => {
@@ -36,35 +57,162 @@ async fn main() -> Result<()> {
         }
     };
 
+    // The daemon owns the selection when clients paste an entry, so it outlives them
+    let writers = clipboard::create_writers(&config);
+
+    // Start the clipboard listeners; they reconnect on their own if a display goes away
+    let mut clipboard_events = clipboard::listen(config.clone(), writers.clone())?;
+    println!("Clipboard listener started");
+
+    if config.capture.backend == Backend::Mock {
+        spawn_stdin_copies();
+    }
+
     // Start D-Bus service
     let buffer_dbus = buffer.clone();
-    let _dbus_connection = dbus_service::start_dbus_service(buffer_dbus).await?;
+    let _dbus_connection =
+        dbus_service::start_dbus_service(buffer_dbus, writers, clipboard_events.handle()).await?;
+
+    // Headless sessions have no hotkey support; clipboard-ui can still be launched directly
+    let _hotkey_manager = match register_hotkey(&config.hotkey.popup) {
+        Ok(manager) => Some(manager),
+        Err(e) => {
+            eprintln!("Failed to register global hotkey: {:#}. Continuing without it.", e);
+            None
+        }
+    };
+
+    // Forward hotkey events into the main loop
+    let (hotkey_sender, mut hotkey_events) = mpsc::unbounded_channel();
+    GlobalHotKeyEvent::set_event_handler(Some(move |event| {
+        hotkey_sender.send(event).ok();
+    }));
+
+    println!("Clipboard daemon started");
+
+    let mut last_trigger = Instant::now();
+    let debounce_duration = Duration::from_millis(500);
+
+    // systemd stops the service with SIGTERM
+    let mut terminate = signal(SignalKind::terminate())?;
 
-    // Start clipboard listener in a separate thread
-    let buffer_clipboard = buffer.clone();
-    let storage_path_clone = storage_path.clone();
+    // Rapid changes update the newest entry, which is saved at most once per window
+    let mut coalescer = Coalescer::new(&config.capture);
+    let coalesce_window = Duration::from_millis(config.capture.coalesce_ms);
+    let mut pending_save: Option<tokio::time::Instant> = None;
 
-    thread::spawn(move || {
-        let mut listener = X11ClipboardListener::new(move |text| {
-            let entry = ClipboardEntry::new(text);
-            let mut buffer = buffer_clipboard.lock().unwrap();
-            buffer.push(entry);
+    loop {
+        let next_expiry = buffer.lock().unwrap().next_expiry();
+        let next_release = coalescer.next_release();
 
-            // Save to persistence after each update
-            if let Err(e) = buffer.save_to_file(&storage_path_clone) {
-                eprintln!("Failed to save clipboard history: {}", e);
+        tokio::select! {
+            event = clipboard_events.next() => match event {
+                Some(Ok(event)) => match record(&buffer, &filters, &mut coalescer, event) {
+                    Decision::Push => {
+                        save(&buffer, &storage_path);
+                        pending_save = None;
+                    }
+                    Decision::Replace(_) => {
+                        // Keep the first deadline so a steady stream still gets saved
+                        pending_save.get_or_insert_with(|| tokio::time::Instant::now() + coalesce_window);
+                    }
+                    Decision::Drop => {}
+                },
+                Some(Err(e)) => eprintln!("Clipboard listener error: {}. Reconnecting.", e),
+                None => break,
+            },
+            Some(event) = hotkey_events.recv() => {
+                // Only respond to key press events, not release
+                if event.state == HotKeyState::Pressed {
+                    // Debounce: ignore if triggered too recently
+                    let now = Instant::now();
+                    if now.duration_since(last_trigger) >= debounce_duration {
+                        last_trigger = now;
+                        println!("Hotkey pressed! Launching UI...");
+
+                        // Launch UI client
+                        if let Err(e) = std::process::Command::new("clipboard-ui").spawn() {
+                            eprintln!("Failed to launch UI: {}. Make sure clipboard-ui is installed.", e);
+                        }
+                    }
+                }
+            },
+            _ = tokio::time::sleep_until(pending_save.unwrap_or_else(tokio::time::Instant::now)),
+                if pending_save.is_some() =>
+            {
+                save(&buffer, &storage_path);
+                pending_save = None;
             }
+            _ = tokio::time::sleep_until(next_release.map_or_else(tokio::time::Instant::now, Into::into)),
+                if next_release.is_some() =>
+            {
+                // Record the last change held back from an application that has slowed down
+                let mut stored = false;
+                while let Some(entry) = coalescer.release(Instant::now()) {
+                    stored |= store(&buffer, &mut coalescer, entry) != Decision::Drop;
+                }
+                if stored {
+                    save(&buffer, &storage_path);
+                    pending_save = None;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline(next_expiry)), if next_expiry.is_some() => {
+                let removed = buffer.lock().unwrap().remove_expired(SystemTime::now());
+                if removed > 0 {
+                    println!("Removed {} expired entries", removed);
+                    save(&buffer, &storage_path);
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+
+    println!("Shutting down");
+    if pending_save.is_some() {
+        save(&buffer, &storage_path);
+    }
 
-            println!("Clipboard updated. Total entries: {}", buffer.len());
-        })
-        .expect("Failed to create clipboard listener");
+    // Blocks until the listener thread has finished
+    let handle = clipboard_events.handle();
+    tokio::task::spawn_blocking(move || handle.stop()).await?;
+    Ok(())
+}
 
-        println!("Clipboard listener started (X11)");
-        listener.start().expect("Clipboard listener failed");
+/// Apply command-line options on top of the config file
+fn apply_args(config: &mut Config, mut args: impl Iterator<Item = String>) -> Result<()> {
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--backend" => {
+                let Some(backend) = args.next() else {
+                    anyhow::bail!("--backend needs a value");
+                };
+                config.capture.backend = backend.parse()?;
+            }
+            _ => anyhow::bail!("Unknown argument: {}", arg),
+        }
+    }
+    Ok(())
+}
+
+/// Feed each line read from stdin to the mock clipboard as a copy, so tests can drive the daemon
+fn spawn_stdin_copies() {
+    std::thread::spawn(|| {
+        for line in std::io::stdin().lock().lines() {
+            match line {
+                Ok(line) => MockClipboard::global().copy(Selection::Clipboard, line),
+                Err(e) => {
+                    eprintln!("Failed to read stdin: {}", e);
+                    break;
+                }
+            }
+        }
     });
+}
 
-    // Parse and register global hotkey from config
-    let (ctrl, shift, alt, key_str) = core_lib::config::parse_hotkey(&config.hotkey.popup)?;
+/// Parse the popup hotkey from the config and register it
+fn register_hotkey(popup: &str) -> Result<GlobalHotKeyManager> {
+    let (ctrl, shift, alt, key_str) = core_lib::config::parse_hotkey(popup)?;
 
     let mut modifiers = Modifiers::empty();
     if ctrl {
@@ -92,30 +240,90 @@ async fn main() -> Result<()> {
     let hotkey_manager = GlobalHotKeyManager::new()?;
     let hotkey = HotKey::new(Some(modifiers), key_code);
     hotkey_manager.register(hotkey)?;
+    Ok(hotkey_manager)
+}
 
-    println!("Clipboard daemon started");
+/// Add a captured clipboard change to the history if it passes the filters, as the coalescer decides
+fn record(
+    buffer: &Mutex<ClipboardBuffer>,
+    filters: &FilterChain,
+    coalescer: &mut Coalescer,
+    event: ClipboardEvent,
+) -> Decision {
+    let entry = ClipboardEntry::new(event.content)
+        .with_timestamp(event.timestamp)
+        .with_selection(event.selection)
+        .with_source(event.source)
+        .with_display(event.display)
+        .with_sensitive(event.sensitive)
+        .with_targets(event.mime_types)
+        .with_raw_targets(event.raw_targets);
+    match filters.apply(entry) {
+        Some(entry) => store(buffer, coalescer, entry),
+        None => Decision::Drop,
+    }
+}
 
-    // Listen for hotkey events
-    let hotkey_receiver = GlobalHotKeyEvent::receiver();
-    let mut last_trigger = Instant::now();
-    let debounce_duration = Duration::from_millis(500);
+/// Add a filtered entry to the history, as the coalescer decides
+fn store(buffer: &Mutex<ClipboardBuffer>, coalescer: &mut Coalescer, entry: ClipboardEntry) -> Decision {
+    let decision = coalescer.decide(&entry, Instant::now());
+    let mut buffer = buffer.lock().unwrap();
+    let id = match decision {
+        Decision::Push => buffer.push(entry),
+        Decision::Replace(id) => buffer.replace_newest(id, entry),
+        Decision::Drop => return decision,
+    };
+    coalescer.recorded(id);
 
-    loop {
-        if let Ok(event) = hotkey_receiver.recv() {
-            // Only respond to key press events, not release
-            if event.state == HotKeyState::Pressed {
-                // Debounce: ignore if triggered too recently
-                let now = Instant::now();
-                if now.duration_since(last_trigger) >= debounce_duration {
-                    last_trigger = now;
-                    println!("Hotkey pressed! Launching UI...");
-
-                    // Launch UI client
-                    if let Err(e) = std::process::Command::new("clipboard-ui").spawn() {
-                        eprintln!("Failed to launch UI: {}. Make sure clipboard-ui is installed.", e);
-                    }
-                }
-            }
-        }
+    println!("Clipboard updated (entry {}). Total entries: {}", id, buffer.len());
+    decision
+}
+
+/// Tokio deadline for a wall-clock time, or now if it has passed
+fn deadline(at: Option<SystemTime>) -> tokio::time::Instant {
+    let wait = at.and_then(|at| at.duration_since(SystemTime::now()).ok()).unwrap_or_default();
+    tokio::time::Instant::now() + wait
+}
+
+/// Persist the history
+fn save(buffer: &Mutex<ClipboardBuffer>, storage_path: &Path) {
+    if let Err(e) = buffer.lock().unwrap().save_to_file(storage_path) {
+        eprintln!("Failed to save clipboard history: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_lib::config::CaptureConfig;
+
+    #[tokio::test]
+    async fn test_mock_capture_is_persisted() {
+        let storage_path = Path::new("/tmp/test_daemon_mock_history.json");
+        std::fs::remove_file(storage_path).ok();
+
+        let mut config = Config::default();
+        apply_args(&mut config, ["--backend".to_string(), "mock".to_string()].into_iter()).unwrap();
+        let mut events = clipboard::listen(config, clipboard::Writers::new()).unwrap();
+        let buffer = Mutex::new(ClipboardBuffer::new(10));
+        let mut coalescer = Coalescer::new(&CaptureConfig::default());
+
+        MockClipboard::global().copy(Selection::Clipboard, "hello".to_string());
+        let event = events.next().await.unwrap().unwrap();
+        let filters = FilterChain::from_config(&Config::default()).unwrap();
+        assert_eq!(record(&buffer, &filters, &mut coalescer, event), Decision::Push);
+        // Mock copies have no owner, so a quick second copy is a new entry
+        MockClipboard::global().copy(Selection::Clipboard, "world".to_string());
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(record(&buffer, &filters, &mut coalescer, event), Decision::Push);
+        save(&buffer, storage_path);
+
+        let loaded = ClipboardBuffer::new_with_persistence(10, storage_path).unwrap();
+        let texts: Vec<_> = loaded.entries().filter_map(ClipboardEntry::text).collect();
+        assert_eq!(texts, vec!["world", "hello"]);
+
+        let handle = events.handle();
+        tokio::task::spawn_blocking(move || handle.stop()).await.unwrap();
+        std::fs::remove_file(storage_path).ok();
     }
 }
@@ -4,16 +4,33 @@ use gtk4::{
     ScrolledWindow, SelectionMode, PolicyType,
 };
 use anyhow::Result;
+use core_lib::buffer::{ClipboardContent, Selection, SourceApp};
 use serde::Deserialize;
 use enigo::{Enigo, Key, Keyboard, Settings};
-use std::io::Write;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Deserialize)]
 struct ClipboardEntry {
-    text: String,
+    id: u64,
+    content: ClipboardContent,
     timestamp: u64,
+    #[serde(default)]
+    selection: Selection,
+    #[serde(default)]
+    source: Option<SourceApp>,
+    #[serde(default)]
+    sensitive: bool,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default)]
+    masked: bool,
 }
 
+/// CSS class marking rows captured from the PRIMARY selection
+const PRIMARY_ROW_CLASS: &str = "primary-selection";
+
 const APP_ID: &str = "com.clipboardhistory.UI";
 
 #[tokio::main]
@@ -110,24 +127,39 @@ fn build_ui(app: &Application, entries: Vec<ClipboardEntry>) {
         }
     }
 
-    // Handle entry selection
+    // Hide PRIMARY entries unless enabled; Ctrl+P toggles them at runtime
+    let show_primary = Rc::new(Cell::new(config.ui.show_primary));
+    let show_primary_filter = show_primary.clone();
+    list_box.set_filter_func(move |row| {
+        show_primary_filter.get() || !row.has_css_class(PRIMARY_ROW_CLASS)
+    });
+
+    // Handle entry selection; rows are in the same order as entries
     let window_clone = window.clone();
+    let entries = Rc::new(entries);
+    let entries_clone = entries.clone();
+    let app_clone = app.clone();
     list_box.connect_row_activated(move |_, row| {
-        if let Some(child) = row.child() {
-            if let Ok(label) = child.downcast::<Label>() {
-                let text = label.text();
-
-                // Close window first
-                window_clone.close();
+        if let Some(entry) = entries_clone.get(row.index() as usize) {
+            // The daemon serves the content, so it survives this window closing.
+            // Entries are addressed by id, as new copies shift their positions
+            if let Err(e) = set_clipboard(entry.id) {
+                eprintln!("Failed to set clipboard: {}", e);
+                return;
+            }
 
-                // Give the window time to close and focus to return to previous app
-                std::thread::sleep(std::time::Duration::from_millis(100));
+            // The application quits with its last window; keep it running until the paste is sent
+            let hold = app_clone.hold();
+            window_clone.close();
 
+            // Give the window time to close and focus to return to previous app
+            glib::timeout_add_local_once(Duration::from_millis(100), move || {
                 // Simulate paste using Enigo
-                if let Err(e) = simulate_paste(&text) {
+                if let Err(e) = simulate_paste() {
                     eprintln!("Failed to simulate paste: {}", e);
                 }
-            }
+                drop(hold);
+            });
         }
     });
 
@@ -142,18 +174,29 @@ fn build_ui(app: &Application, entries: Vec<ClipboardEntry>) {
     main_box.append(&scrolled_window);
 
     // Add instructions
-    let instructions = Label::new(Some("Press Enter or click to paste, Esc to cancel"));
+    let has_primary = entries.iter().any(|e| e.selection == Selection::Primary);
+    let instructions = if has_primary {
+        "Press Enter or click to paste, Ctrl+P to toggle selections, Esc to cancel"
+    } else {
+        "Press Enter or click to paste, Esc to cancel"
+    };
+    let instructions = Label::new(Some(instructions));
     instructions.add_css_class("dim-label");
     instructions.add_css_class("caption");
     main_box.append(&instructions);
 
-    // Handle Escape key to close window
+    // Handle Escape to close the window and Ctrl+P to toggle PRIMARY entries
     let event_controller = gtk4::EventControllerKey::new();
     let window_clone = window.clone();
-    event_controller.connect_key_pressed(move |_, key, _, _| {
+    let list_box_clone = list_box.clone();
+    event_controller.connect_key_pressed(move |_, key, _, state| {
         if key == gtk4::gdk::Key::Escape {
             window_clone.close();
             glib::Propagation::Stop
+        } else if key == gtk4::gdk::Key::p && state.contains(gtk4::gdk::ModifierType::CONTROL_MASK) {
+            show_primary.set(!show_primary.get());
+            list_box_clone.invalidate_filter();
+            glib::Propagation::Stop
         } else {
             glib::Propagation::Proceed
         }
@@ -171,10 +214,15 @@ fn create_entry_row(entry: &ClipboardEntry) -> ListBoxRow {
     let row = ListBoxRow::new();
 
     // Truncate text for display (show first 100 chars)
-    let display_text = if entry.text.len() > 100 {
-        format!("{}...", &entry.text[..100])
+    let summary = if entry.sensitive || entry.masked {
+        "••••••••".to_string()
     } else {
-        entry.text.clone()
+        entry.content.summary()
+    };
+    let display_text = if summary.chars().count() > 100 {
+        format!("{}...", summary.chars().take(100).collect::<String>())
+    } else {
+        summary
     };
 
     // Replace newlines with spaces for single-line display
@@ -187,28 +235,71 @@ fn create_entry_row(entry: &ClipboardEntry) -> ListBoxRow {
     label.set_margin_start(12);
     label.set_margin_end(12);
     label.set_ellipsize(gtk4::pango::EllipsizeMode::End);
+    label.set_hexpand(true);
+
+    let mut tooltip = Vec::new();
+    if entry.sensitive || entry.masked {
+        label.add_css_class("dim-label");
+        tooltip.push("Hidden: sensitive content");
+    } else if let ClipboardContent::Image(_) | ClipboardContent::Files(_) = entry.content {
+        label.add_css_class("dim-label");
+    } else if entry.content.is_rich_text() {
+        tooltip.push("Formatted text");
+    }
+
+    if entry.selection == Selection::Primary {
+        row.add_css_class(PRIMARY_ROW_CLASS);
+        tooltip.push("Highlighted text (PRIMARY selection)");
+    }
+    if !tooltip.is_empty() {
+        row.set_tooltip_text(Some(&tooltip.join("\n")));
+    }
 
-    // Store full text in label for retrieval on activation
-    label.set_text(&entry.text);
+    let row_box = GtkBox::new(Orientation::Horizontal, 0);
+    if entry.pinned {
+        let pin_label = Label::new(Some("📌"));
+        pin_label.set_margin_start(12);
+        pin_label.set_tooltip_text(Some("Pinned: never removed from the history"));
+        row_box.append(&pin_label);
+    }
+    row_box.append(&label);
+
+    // Where the entry was copied from
+    if let Some(name) = entry.source.as_ref().and_then(SourceApp::name) {
+        let source_label = Label::new(Some(name));
+        source_label.add_css_class("dim-label");
+        source_label.set_margin_end(12);
+        row_box.append(&source_label);
+    }
 
-    row.set_child(Some(&label));
+    row.set_child(Some(&row_box));
     row
 }
 
-fn simulate_paste(text: &str) -> Result<()> {
-    // Use xclip to set clipboard (more reliable than enigo for clipboard)
-    std::process::Command::new("xclip")
-        .args(&["-selection", "clipboard"])
-        .stdin(std::process::Stdio::piped())
-        .spawn()?
-        .stdin
-        .as_mut()
-        .unwrap()
-        .write_all(text.as_bytes())?;
+/// Ask the daemon to put the entry with `id` on the clipboard
+fn set_clipboard(id: u64) -> Result<()> {
+    let connection = zbus::blocking::Connection::session()?;
+
+    let proxy = zbus::blocking::Proxy::new(
+        &connection,
+        "com.clipboardhistory.Service",
+        "/com/clipboardhistory/Service",
+        "com.clipboardhistory.Service",
+    )?;
+
+    // Paste on the display the popup was opened on when the daemon watches several
+    let ok: bool = match std::env::var("DISPLAY") {
+        Ok(display) => proxy.call("SetClipboardOnDisplay", &(id, display.as_str()))?,
+        Err(_) => proxy.call("SetClipboard", &(id,))?,
+    };
+    if !ok {
+        anyhow::bail!("The daemon could not set the clipboard");
+    }
 
-    // Small delay to ensure clipboard is set
-    std::thread::sleep(std::time::Duration::from_millis(50));
+    Ok(())
+}
 
+fn simulate_paste() -> Result<()> {
     // Simulate Ctrl+V using enigo
     let mut enigo = Enigo::new(&Settings::default())
         .map_err(|e| anyhow::anyhow!("Failed to create Enigo: {:?}", e))?;
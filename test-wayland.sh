@@ -0,0 +1,28 @@
+#!/bin/bash
+# Run the Wayland backend tests against a headless wlroots compositor (sway).
+# Requires sway and wl-clipboard to be installed.
+
+set -e  # Exit on error
+
+# Use a private runtime dir so we never talk to the session's compositor
+export XDG_RUNTIME_DIR=$(mktemp -d)
+unset WAYLAND_DISPLAY DISPLAY
+
+WLR_BACKENDS=headless WLR_LIBINPUT_NO_DEVICES=1 sway -c /dev/null &
+SWAY_PID=$!
+trap 'kill $SWAY_PID; rm -rf "$XDG_RUNTIME_DIR"' EXIT
+
+# Wait for the compositor socket to appear
+for _ in $(seq 50); do
+    SOCKET=$(find "$XDG_RUNTIME_DIR" -maxdepth 1 -name 'wayland-*' ! -name '*.lock' | head -n 1)
+    [ -n "$SOCKET" ] && break
+    sleep 0.1
+done
+
+if [ -z "$SOCKET" ]; then
+    echo "Error: headless sway did not start"
+    exit 1
+fi
+
+export WAYLAND_DISPLAY=$(basename "$SOCKET")
+cargo test -p clipboard -- --ignored wayland